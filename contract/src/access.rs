@@ -0,0 +1,195 @@
+use crate::*;
+use near_sdk::env;
+
+impl Room {
+    /// Checks that the player can join the room with the given access code.
+    /// The public rooms don't require any code.
+    pub fn assert_access_code(&self, access_code: Option<String>) {
+        if !self.is_hidden {
+            return;
+        }
+
+        let access_code_hash = match self.access_code_hash {
+            None => panic!("The room is hidden"),
+            Some(access_code_hash) => access_code_hash,
+        };
+        let access_code = access_code.expect("The access code is required");
+        if env::sha256_array(access_code.as_bytes()) != access_code_hash {
+            panic!("Invalid access code")
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the hash of the new access code of the hidden room. The previous code stops working.
+    #[payable]
+    pub fn set_access_code(
+        &mut self,
+        room_id: RoomId,
+        app_name: AppName,
+        access_code_hash: Option<Base58CryptoHash>,
+    ) {
+        self.assert_app_not_paused(&app_name);
+        let player_id = predecessor_account_id();
+        let room = self.internal_get_app_room(&room_id, &app_name);
+
+        room.assert_permission(&player_id, Permission::SetAccessCode);
+        if !room.is_hidden {
+            panic!("The access code can be set only for the hidden room")
+        }
+
+        let mut account = self.internal_unwrap_account_or_create(&player_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.access_code_hash = access_code_hash.map(CryptoHash::from);
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&player_id, account);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    const ACCESS_CODE: &str = "secret";
+
+    fn access_code_hash(access_code: &str) -> Base58CryptoHash {
+        env::sha256_array(access_code.as_bytes()).into()
+    }
+
+    fn setup_hidden_room(contract: &mut Contract) -> RoomId {
+        let mut config = room_config(APP_NAME, 3);
+        config.is_hidden = true;
+        config.access_code_hash = Some(access_code_hash(ACCESS_CODE));
+        setup_app_room(contract, app_config(), config, 1)
+    }
+
+    #[test]
+    fn test_hidden_room_is_not_available() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+        assert!(contract
+            .get_app_rooms(APP_NAME.to_string(), None, Some(10))
+            .is_empty());
+
+        set_context(&accounts(0), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        contract.open(room_id, APP_NAME.to_string());
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "There are currently no available rooms")]
+    fn test_random_join_skips_hidden_room() {
+        let mut contract = new_contract();
+        setup_hidden_room(&mut contract);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.random_join(APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_join_hidden_room_with_access_code() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), Some(ACCESS_CODE.to_string()));
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.players, vec![accounts(0), accounts(1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid access code")]
+    fn test_join_hidden_room_with_invalid_access_code() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), Some("guess".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "The access code is required")]
+    fn test_join_hidden_room_without_access_code() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid access code")]
+    fn test_rotated_access_code() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(0), 0);
+        contract.set_access_code(
+            room_id,
+            APP_NAME.to_string(),
+            Some(access_code_hash("new secret")),
+        );
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(
+            room_id,
+            APP_NAME.to_string(),
+            Some("new secret".to_string()),
+        );
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), Some(ACCESS_CODE.to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "The room is hidden")]
+    fn test_join_hidden_room_after_removing_access_code() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(0), 0);
+        contract.set_access_code(room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), Some(ACCESS_CODE.to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can set the access code")]
+    fn test_set_access_code_not_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_hidden_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_access_code(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The access code can be set only for the hidden room")]
+    fn test_access_code_for_public_room() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        let mut config = room_config(APP_NAME, 3);
+        config.access_code_hash = Some(access_code_hash(ACCESS_CODE));
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(config);
+    }
+}
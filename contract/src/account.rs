@@ -3,7 +3,7 @@ use crate::*;
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
-use near_sdk::{env, require, Balance, StorageUsage};
+use near_sdk::{assert_one_yocto, env, log, require, Balance, StorageUsage};
 
 pub const MIN_STORAGE_BYTES: StorageUsage = 2000;
 const MIN_STORAGE_BALANCE: Balance = MIN_STORAGE_BYTES as Balance * env::STORAGE_PRICE_PER_BYTE;
@@ -35,10 +35,25 @@ impl Account {
         self.storage_tracker.stop();
     }
 
+    /// Returns the part of the storage balance that is not locked by the used storage.
+    pub fn available_balance(&self) -> Balance {
+        self.storage_balance.saturating_sub(self.locked_balance())
+    }
+
+    /// Whether the storage balance covers the used storage together with the tracked one.
+    pub fn is_tracked_storage_covered(&self) -> bool {
+        let used_bytes = (self.used_bytes + self.storage_tracker.bytes_added)
+            .saturating_sub(self.storage_tracker.bytes_released);
+        Balance::from(used_bytes) * env::storage_byte_cost() <= self.storage_balance
+    }
+
+    fn locked_balance(&self) -> Balance {
+        Balance::from(self.used_bytes) * env::storage_byte_cost()
+    }
+
     fn assert_storage_covered(&self) {
-        let storage_balance_needed = Balance::from(self.used_bytes) * env::storage_byte_cost();
         assert!(
-            storage_balance_needed <= self.storage_balance,
+            self.locked_balance() <= self.storage_balance,
             "Not enough storage balance"
         );
     }
@@ -46,10 +61,20 @@ impl Account {
 
 impl Contract {
     pub fn internal_get_account(&self, account_id: &AccountId) -> Account {
-        self.accounts
-            .get(account_id)
+        self.internal_get_account_opt(account_id)
             .expect("Account not found")
-            .clone()
+    }
+
+    /// Returns the account in the current layout, upgrading the legacy one.
+    pub(crate) fn internal_get_account_opt(&self, account_id: &AccountId) -> Option<Account> {
+        match self.accounts.get(account_id) {
+            Some(account) => Some(account.clone().into()),
+            None => self.legacy_accounts.get(account_id).cloned(),
+        }
+    }
+
+    fn internal_has_account(&self, account_id: &AccountId) -> bool {
+        self.accounts.contains_key(account_id) || self.legacy_accounts.contains_key(account_id)
     }
 
     pub fn internal_unwrap_account_or_create(
@@ -62,14 +87,14 @@ impl Contract {
             "Invalid account id"
         );
 
-        return if !self.accounts.contains_key(account_id) {
+        if !self.internal_has_account(account_id) {
             self.internal_create_account(account_id, storage_deposit, false);
             self.internal_get_account(account_id)
         } else {
             let mut account: Account = self.internal_get_account(account_id);
             account.storage_balance += storage_deposit;
             account
-        };
+        }
     }
 
     pub fn internal_create_account(
@@ -79,9 +104,10 @@ impl Contract {
         registration_only: bool,
     ) {
         let min_balance = self.storage_balance_bounds().min.0;
-        if storage_deposit < min_balance {
-            env::panic_str("The attached deposit is less than the minimum storage balance");
-        }
+        require!(
+            storage_deposit >= min_balance,
+            "The attached deposit is less than the minimum storage balance"
+        );
 
         let mut account = Account::new();
         if registration_only {
@@ -114,28 +140,106 @@ impl Contract {
         }
         account.storage_tracker.bytes_released = 0;
         account.storage_tracker.bytes_added = 0;
+        let is_legacy = self.legacy_accounts.remove(account_id).is_some();
         self.accounts
             .insert(account_id.clone(), account.into())
             .is_some()
+            || is_legacy
     }
 }
 
 #[near_bindgen]
 impl StorageManagement for Contract {
+    #[payable]
     fn storage_deposit(
         &mut self,
         account_id: Option<AccountId>,
         registration_only: Option<bool>,
     ) -> StorageBalance {
-        todo!()
+        self.assert_not_paused();
+        let amount = attached_deposit();
+        let account_id = account_id.unwrap_or_else(predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+
+        if self.internal_has_account(&account_id) {
+            if registration_only {
+                log!("The account is already registered, refunding the deposit");
+                if amount > 0 {
+                    Promise::new(predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                let mut account = self.internal_get_account(&account_id);
+                account.storage_balance += amount;
+                self.internal_set_account(&account_id, account);
+            }
+        } else {
+            self.internal_create_account(&account_id, amount, registration_only);
+        }
+
+        self.storage_balance_of(account_id).unwrap()
     }
 
+    #[payable]
     fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
-        todo!()
+        self.assert_not_paused();
+        assert_one_yocto();
+        let account_id = predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        let available = account.available_balance();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        require!(
+            amount <= available,
+            "The amount is greater than the available storage balance"
+        );
+
+        account.storage_balance -= amount;
+        self.internal_set_account(&account_id, account);
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+
+        self.storage_balance_of(account_id).unwrap()
     }
 
+    #[payable]
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
-        todo!()
+        self.assert_not_paused();
+        assert_one_yocto();
+        let account_id = predecessor_account_id();
+        let force = force.unwrap_or(false);
+
+        let mut account = match self.internal_get_account_opt(&account_id) {
+            None => {
+                log!("The account {} is not registered", &account_id);
+                return false;
+            }
+            Some(account) => account,
+        };
+
+        if force {
+            // The storage the account pays for outside of its rooms stays, so such an account
+            // can't be unregistered.
+            self.internal_remove_account_from_rooms(&account_id);
+            self.internal_remove_room_limits_with_refund(&account_id);
+            account = self.internal_get_account(&account_id);
+            require!(
+                account.used_bytes == 0,
+                "Can't unregister the account that pays for the storage outside of its rooms"
+            );
+        } else {
+            require!(
+                account.used_bytes == 0,
+                "Can't unregister the account with the positive used storage without force"
+            );
+        }
+
+        self.accounts.remove(&account_id);
+        self.legacy_accounts.remove(&account_id);
+        if account.storage_balance > 0 {
+            Promise::new(account_id).transfer(account.storage_balance);
+        }
+
+        true
     }
 
     fn storage_balance_bounds(&self) -> StorageBalanceBounds {
@@ -146,6 +250,210 @@ impl StorageManagement for Contract {
     }
 
     fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
-        todo!()
+        self.internal_get_account_opt(&account_id)
+            .map(|account| StorageBalance {
+                total: U128(account.storage_balance),
+                available: U128(account.available_balance()),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::ONE_YOCTO;
+
+    #[test]
+    fn test_storage_deposit_registers_account() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total.0, DEPOSIT);
+        assert_eq!(balance.available.0, DEPOSIT);
+        assert_eq!(
+            contract.storage_balance_of(accounts(0)).unwrap().total.0,
+            DEPOSIT
+        );
+    }
+
+    #[test]
+    fn test_storage_deposit_for_another_account() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+
+        contract.storage_deposit(Some(accounts(1)), None);
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
+        assert_eq!(
+            contract.storage_balance_of(accounts(1)).unwrap().total.0,
+            DEPOSIT
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_storage_deposit_less_than_min() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), MIN_STORAGE_BALANCE - 1);
+
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only_refunds_excess() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, MIN_STORAGE_BALANCE);
+        assert_eq!(transferred_to(&accounts(0)), DEPOSIT - MIN_STORAGE_BALANCE);
+
+        set_context(&accounts(0), DEPOSIT);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, MIN_STORAGE_BALANCE);
+        assert_eq!(transferred_to(&accounts(0)), DEPOSIT);
+    }
+
+    #[test]
+    fn test_storage_deposit_tops_up_existing_account() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+        contract.storage_deposit(None, None);
+
+        set_context(&accounts(0), DEPOSIT);
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total.0, 2 * DEPOSIT);
+    }
+
+    #[test]
+    fn test_storage_withdraw_available_balance() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        let account = contract.internal_get_account(&accounts(0));
+        assert!(account.used_bytes > 0);
+        let locked = Balance::from(account.used_bytes) * env::storage_byte_cost();
+
+        set_context(&accounts(0), ONE_YOCTO);
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.total.0, locked);
+        assert_eq!(balance.available.0, 0);
+        assert_eq!(transferred_to(&accounts(0)), DEPOSIT - locked);
+    }
+
+    #[test]
+    fn test_storage_withdraw_partial_amount() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+        contract.storage_deposit(None, None);
+
+        set_context(&accounts(0), ONE_YOCTO);
+        let balance = contract.storage_withdraw(Some(U128(DEPOSIT / 4)));
+        assert_eq!(balance.total.0, DEPOSIT - DEPOSIT / 4);
+        assert_eq!(transferred_to(&accounts(0)), DEPOSIT / 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount is greater than the available storage balance")]
+    fn test_storage_withdraw_more_than_available() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(0), ONE_YOCTO);
+        contract.storage_withdraw(Some(U128(DEPOSIT)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_storage_withdraw_requires_one_yocto() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+        contract.storage_deposit(None, None);
+
+        set_context(&accounts(0), 0);
+        contract.storage_withdraw(None);
+    }
+
+    #[test]
+    fn test_storage_unregister_not_registered() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), ONE_YOCTO);
+
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    fn test_storage_unregister_refunds_balance() {
+        let mut contract = new_contract();
+        set_context(&accounts(0), DEPOSIT);
+        contract.storage_deposit(None, None);
+
+        set_context(&accounts(0), ONE_YOCTO);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
+        assert_eq!(transferred_to(&accounts(0)), DEPOSIT);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with the positive used storage")]
+    fn test_storage_unregister_with_used_storage() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(0), ONE_YOCTO);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_storage_unregister_force_leaves_all_rooms() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        register_app(&mut contract, &accounts(5), "other_app");
+        set_context(&accounts(0), DEPOSIT);
+        let alice_room_id = contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(1), DEPOSIT);
+        let bob_room_id = contract.create_room(room_config("other_app", 2));
+        set_context(&accounts(1), 0);
+        contract.join(alice_room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(bob_room_id, "other_app".to_string(), None);
+
+        set_context(&accounts(1), ONE_YOCTO);
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.internal_get_account(&accounts(2)).used_bytes, 0);
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+        assert_eq!(transferred_to(&accounts(1)), DEPOSIT);
+
+        let alice_room = contract.rooms.get(&alice_room_id).unwrap();
+        assert_eq!(alice_room.players, vec![accounts(0)]);
+        assert!(contract.rooms.get(&bob_room_id).is_none());
+        assert_eq!(
+            contract.get_number_of_available_rooms("other_app".to_string()),
+            0
+        );
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(1))
+            .is_none());
+        assert!(contract
+            .get_app_account_room("other_app".to_string(), accounts(2))
+            .is_none());
+    }
+
+    #[test]
+    fn test_storage_balance_of_not_registered() {
+        let contract = new_contract();
+        set_context(&accounts(0), 0);
+
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
     }
 }
@@ -0,0 +1,393 @@
+use crate::*;
+use near_sdk::require;
+
+const DEFAULT_ROOM_LIMIT: u32 = 1;
+
+/// The rooms the account is in across all apps.
+#[derive(Clone, Default, BorshDeserialize, BorshSerialize)]
+pub struct AccountRooms {
+    pub rooms: Vec<(AppName, RoomId)>,
+    /// The apps in which the account opted in to be in more than one room at once.
+    pub room_limits: Vec<(AppName, u32)>,
+}
+
+impl AccountRooms {
+    fn count_in_app(&self, app_name: &AppName) -> u32 {
+        self.rooms
+            .iter()
+            .filter(|(room_app_name, _)| room_app_name.eq(app_name))
+            .count() as u32
+    }
+
+    fn room_limit(&self, app_name: &AppName) -> u32 {
+        self.room_limits
+            .iter()
+            .find(|(limit_app_name, _)| limit_app_name.eq(app_name))
+            .map_or(DEFAULT_ROOM_LIMIT, |(_, room_limit)| *room_limit)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rooms.is_empty() && self.room_limits.is_empty()
+    }
+}
+
+impl Contract {
+    /// Returns the ids of the rooms the account is in within the app, from the earliest joined.
+    pub(crate) fn internal_get_account_room_ids(
+        &self,
+        app_name: &AppName,
+        account_id: &AccountId,
+    ) -> Vec<RoomId> {
+        self.account_rooms
+            .get(account_id)
+            .map(|account_rooms| {
+                account_rooms
+                    .rooms
+                    .iter()
+                    .filter(|(room_app_name, _)| room_app_name.eq(app_name))
+                    .map(|(_, room_id)| *room_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the error if the account is in as many rooms of the app as it can be.
+    pub(crate) fn check_can_enter_room(
+        &self,
+        app_name: &AppName,
+        account_id: &AccountId,
+    ) -> Result<(), String> {
+        let account_rooms = match self.account_rooms.get(account_id) {
+            None => return Ok(()),
+            Some(account_rooms) => account_rooms,
+        };
+        // The app owner can lower the max after the account opted in to more rooms.
+        let room_limit = account_rooms
+            .room_limit(app_name)
+            .min(self.internal_get_app(app_name).max_rooms_per_account);
+        if account_rooms.count_in_app(app_name) < room_limit {
+            return Ok(());
+        }
+        if room_limit == DEFAULT_ROOM_LIMIT {
+            return Err("Account is already in the room".to_string());
+        }
+        Err("Account is already in the max number of rooms".to_string())
+    }
+
+    /// Adds the room to the rooms of the account. The account pays for the entry.
+    pub(crate) fn internal_add_account_room(
+        &mut self,
+        app_name: &AppName,
+        account_id: &AccountId,
+        room_id: RoomId,
+    ) {
+        if let Err(err) = self.check_can_enter_room(app_name, account_id) {
+            panic!("{}", err)
+        }
+
+        self.account_rooms
+            .entry(account_id.clone())
+            .or_default()
+            .rooms
+            .push((app_name.clone(), room_id));
+        self.account_rooms.flush();
+    }
+
+    pub(crate) fn internal_remove_account_room(
+        &mut self,
+        account_id: &AccountId,
+        room_id: &RoomId,
+    ) {
+        let account_rooms = match self.account_rooms.get_mut(account_id) {
+            None => return,
+            Some(account_rooms) => account_rooms,
+        };
+        account_rooms.rooms.retain(|(_, x)| x.ne(room_id));
+        if account_rooms.is_empty() {
+            self.account_rooms.remove(account_id);
+        }
+        self.account_rooms.flush();
+    }
+
+    /// Resets the room limits of the account to the default one and returns their storage to the
+    /// account.
+    pub(crate) fn internal_remove_room_limits_with_refund(&mut self, account_id: &AccountId) {
+        if !self.account_rooms.contains_key(account_id) {
+            return;
+        }
+        let mut account = self.internal_get_account(account_id);
+        account.start_storage_tracker();
+
+        let account_rooms = self.account_rooms.get_mut(account_id).unwrap();
+        account_rooms.room_limits.clear();
+        if account_rooms.is_empty() {
+            self.account_rooms.remove(account_id);
+        }
+        self.account_rooms.flush();
+
+        account.stop_storage_tracker();
+        self.internal_set_account(account_id, account);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the number of rooms of the app the caller can be in at once, up to the max of the
+    /// app. The caller pays for the storage of the limit other than the default one.
+    #[payable]
+    pub fn set_room_limit(&mut self, app_name: AppName, room_limit: u32) {
+        self.assert_app_not_paused(&app_name);
+        require!(room_limit > 0, "The room limit must be positive");
+        require!(
+            room_limit <= self.internal_get_app(&app_name).max_rooms_per_account,
+            "The room limit exceeds the max rooms per account of the app"
+        );
+
+        let account_id = predecessor_account_id();
+        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let account_rooms = self.account_rooms.entry(account_id.clone()).or_default();
+        account_rooms
+            .room_limits
+            .retain(|(limit_app_name, _)| limit_app_name.ne(&app_name));
+        if room_limit != DEFAULT_ROOM_LIMIT {
+            account_rooms.room_limits.push((app_name, room_limit));
+        }
+        if account_rooms.is_empty() {
+            self.account_rooms.remove(&account_id);
+        }
+
+        self.account_rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&account_id, account);
+    }
+
+    pub fn get_room_limit(&self, app_name: AppName, account_id: AccountId) -> u32 {
+        self.account_rooms
+            .get(&account_id)
+            .map_or(DEFAULT_ROOM_LIMIT, |account_rooms| {
+                account_rooms.room_limit(&app_name)
+            })
+    }
+
+    /// Returns the rooms the account is in across all apps, from the earliest joined.
+    pub fn get_account_rooms(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<(AppName, Room)> {
+        let rooms = match self.account_rooms.get(&account_id) {
+            None => return Vec::new(),
+            Some(account_rooms) => &account_rooms.rooms,
+        };
+        let start = from_index.map(|from_index| from_index.0).unwrap_or(0);
+
+        rooms
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(rooms.len() as u64) as usize)
+            .map(|(app_name, room_id)| {
+                (
+                    app_name.clone(),
+                    Room::clone(self.rooms.get(room_id).expect("Room id not found")),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppConfig;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+
+    fn multi_room_app_config() -> AppConfig {
+        let mut config = app_config();
+        config.max_rooms_per_account = Some(3);
+        config
+    }
+
+    fn account_room_ids(contract: &Contract, account_id: AccountId) -> Vec<(AppName, RoomId)> {
+        contract
+            .get_account_rooms(account_id, None, None)
+            .into_iter()
+            .map(|(app_name, room)| (app_name, room.room_id))
+            .collect()
+    }
+
+    fn create_rooms(contract: &mut Contract) -> (RoomId, RoomId) {
+        register_app(contract, &accounts(5), APP_NAME);
+        register_app(contract, &accounts(5), "other_app");
+        set_context(&accounts(0), DEPOSIT);
+        let first_room_id = contract.create_room(room_config(APP_NAME, 3));
+        let second_room_id = contract.create_room(room_config("other_app", 3));
+        (first_room_id, second_room_id)
+    }
+
+    #[test]
+    fn test_get_account_rooms() {
+        let mut contract = new_contract();
+        let (first_room_id, second_room_id) = create_rooms(&mut contract);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(first_room_id, APP_NAME.to_string(), None);
+        contract.join(second_room_id, "other_app".to_string(), None);
+        assert_eq!(
+            account_room_ids(&contract, accounts(1)),
+            vec![
+                (APP_NAME.to_string(), first_room_id),
+                ("other_app".to_string(), second_room_id)
+            ]
+        );
+        let rooms = contract.get_account_rooms(accounts(1), Some(U128(1)), Some(1));
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].1.room_id, second_room_id);
+
+        contract.leave(first_room_id, APP_NAME.to_string());
+        assert_eq!(
+            account_room_ids(&contract, accounts(1)),
+            vec![("other_app".to_string(), second_room_id)]
+        );
+    }
+
+    #[test]
+    fn test_account_rooms_follow_kick_and_remove() {
+        let mut contract = new_contract();
+        let (first_room_id, second_room_id) = create_rooms(&mut contract);
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(first_room_id, APP_NAME.to_string(), None);
+        contract.join(second_room_id, "other_app".to_string(), None);
+
+        set_context(&accounts(0), 0);
+        contract.kick(first_room_id, accounts(1));
+        contract.remove(second_room_id, "other_app".to_string());
+        assert!(account_room_ids(&contract, accounts(1)).is_empty());
+        assert_eq!(
+            account_room_ids(&contract, accounts(0)),
+            vec![(APP_NAME.to_string(), first_room_id)]
+        );
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the room")]
+    fn test_join_second_room_without_opt_in() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 3));
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 3));
+
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_join_multiple_rooms_after_opt_in() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), multi_room_app_config());
+        set_context(&accounts(0), DEPOSIT);
+        let first_room_id = contract.create_room(room_config(APP_NAME, 3));
+        set_context(&accounts(2), DEPOSIT);
+        let second_room_id = contract.create_room(room_config(APP_NAME, 3));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 2);
+        assert_eq!(
+            contract.get_room_limit(APP_NAME.to_string(), accounts(1)),
+            2
+        );
+        contract.join(first_room_id, APP_NAME.to_string(), None);
+        contract.join(second_room_id, APP_NAME.to_string(), None);
+        assert_eq!(
+            contract.internal_get_account_room_ids(&APP_NAME.to_string(), &accounts(1)),
+            vec![first_room_id, second_room_id]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the max number of rooms")]
+    fn test_join_over_room_limit() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), multi_room_app_config());
+        let mut room_ids = Vec::new();
+        for idx in 0..3 {
+            set_context(&accounts(idx), DEPOSIT);
+            room_ids.push(contract.create_room(room_config(APP_NAME, 3)));
+        }
+
+        set_context(&accounts(4), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 2);
+        for room_id in room_ids {
+            contract.join(room_id, APP_NAME.to_string(), None);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The room limit exceeds the max rooms per account of the app")]
+    fn test_room_limit_over_app_max() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 2);
+    }
+
+    #[test]
+    fn test_reset_room_limit_refunds_storage() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), multi_room_app_config());
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 3);
+        assert!(used_bytes(&contract, &accounts(1)) > 0);
+
+        contract.set_room_limit(APP_NAME.to_string(), 1);
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+        assert_eq!(
+            contract.get_room_limit(APP_NAME.to_string(), accounts(1)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_storage_unregister_force_removes_room_limits() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), multi_room_app_config());
+        set_context(&accounts(1), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 3);
+        contract.create_room(room_config(APP_NAME, 3));
+
+        set_context(&accounts(1), 1);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.account_rooms.get(&accounts(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the room")]
+    fn test_lowered_app_max_caps_room_limit() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), multi_room_app_config());
+        set_context(&accounts(0), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 3));
+        set_context(&accounts(1), DEPOSIT);
+        contract.set_room_limit(APP_NAME.to_string(), 2);
+        contract.create_room(room_config(APP_NAME, 3));
+
+        set_context(&accounts(5), 0);
+        contract.update_app(APP_NAME.to_string(), app_config());
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+}
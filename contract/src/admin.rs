@@ -0,0 +1,355 @@
+use crate::*;
+use near_sdk::log;
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractConfig {
+    /// Anyone can register the apps when set, otherwise only the contract owner.
+    pub is_app_registration_open: bool,
+}
+
+impl Default for ContractConfig {
+    fn default() -> Self {
+        Self {
+            is_app_registration_open: true,
+        }
+    }
+}
+
+impl Contract {
+    fn assert_owner(&self) {
+        if self.owner_id != predecessor_account_id() {
+            panic!("Only the contract owner can call the method")
+        }
+    }
+
+    /// Checks that the mutating methods are not paused.
+    pub(crate) fn assert_not_paused(&self) {
+        if self.is_paused {
+            panic!("The contract is paused")
+        }
+    }
+
+    /// Checks that neither the contract nor the app is paused.
+    pub(crate) fn assert_app_not_paused(&self, app_name: &AppName) {
+        self.assert_not_paused();
+        if self.internal_get_app(app_name).is_paused {
+            panic!("The app is paused")
+        }
+    }
+
+    /// Same as [`Contract::assert_app_not_paused`] for the app of the room.
+    pub(crate) fn assert_room_not_paused(&self, room_id: &RoomId) {
+        let room = self.rooms.get(room_id).expect("Room id not found");
+        self.assert_app_not_paused(&room.app_name);
+    }
+
+    fn internal_set_app_paused(&mut self, app_name: &AppName, is_paused: bool) {
+        let app = self.internal_get_app(app_name);
+        let caller_id = predecessor_account_id();
+        if app.owner_id != caller_id && self.owner_id != caller_id {
+            panic!("Only the app owner or the contract owner can pause the app")
+        }
+        self.apps.get_mut(app_name).unwrap().is_paused = is_paused;
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Blocks all mutating methods except the admin ones. The views keep working.
+    pub fn pause(&mut self) {
+        self.assert_owner();
+        self.is_paused = true;
+        log!("The contract is paused");
+    }
+
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        self.is_paused = false;
+        log!("The contract is unpaused");
+    }
+
+    /// Blocks the mutating methods in the rooms of the app. The app owner can still update the
+    /// app and manage the app bans.
+    pub fn pause_app(&mut self, app_name: AppName) {
+        self.internal_set_app_paused(&app_name, true);
+        log!("The app {} is paused", app_name);
+    }
+
+    pub fn unpause_app(&mut self, app_name: AppName) {
+        self.internal_set_app_paused(&app_name, false);
+        log!("The app {} is unpaused", app_name);
+    }
+
+    /// Removes the room regardless of its status, e.g. when handling the abuse reports.
+    /// Works while the contract is paused. The players get back their entry fees and storage.
+    pub fn emergency_close_room(&mut self, room_id: RoomId, reason: String) {
+        self.assert_owner();
+        let app_name = self
+            .rooms
+            .get(&room_id)
+            .expect("Room id not found")
+            .app_name
+            .clone();
+
+        log!(
+            "The room {} is closed by the contract owner: {}",
+            room_id,
+            reason
+        );
+        self.internal_remove_room(&room_id, &app_name);
+    }
+
+    pub fn update_config(&mut self, config: ContractConfig) {
+        self.assert_owner();
+        self.config = config;
+    }
+
+    pub fn get_owner_id(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_config(&self) -> ContractConfig {
+        self.config.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::ONE_NEAR;
+
+    fn owner_id() -> AccountId {
+        "owner.near".parse().unwrap()
+    }
+
+    fn owned_contract() -> Contract {
+        set_context(&owner_id(), 0);
+        Contract::new(owner_id(), ContractConfig::default())
+    }
+
+    fn paused_contract() -> (Contract, RoomId) {
+        let mut contract = owned_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&owner_id(), 0);
+        contract.pause();
+        (contract, room_id)
+    }
+
+    fn app_paused_contract() -> (Contract, RoomId) {
+        let mut contract = owned_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(5), 0);
+        contract.pause_app(APP_NAME.to_string());
+        (contract, room_id)
+    }
+
+    #[test]
+    fn test_new() {
+        let contract = owned_contract();
+        assert_eq!(contract.get_owner_id(), owner_id());
+        assert!(contract.get_config().is_app_registration_open);
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can call the method")]
+    fn test_pause_not_owner() {
+        let mut contract = owned_contract();
+
+        set_context(&accounts(0), 0);
+        contract.pause();
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_create_room_paused() {
+        let (mut contract, _) = paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_join_paused() {
+        let (mut contract, room_id) = paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_leave_paused() {
+        let (mut contract, room_id) = paused_contract();
+
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_kick_paused() {
+        let (mut contract, room_id) = paused_contract();
+
+        set_context(&accounts(0), 0);
+        contract.kick(room_id, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_register_app_paused() {
+        let (mut contract, _) = paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.register_app("other_app".to_string(), app_config());
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_storage_deposit_paused() {
+        let (mut contract, _) = paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_enqueue_paused() {
+        let (mut contract, _) = paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.enqueue(APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_views_work_while_paused() {
+        let (contract, room_id) = paused_contract();
+
+        assert!(contract.is_paused());
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            1
+        );
+        assert_eq!(
+            contract
+                .get_app_account_room(APP_NAME.to_string(), accounts(0))
+                .unwrap()
+                .room_id,
+            room_id
+        );
+        assert!(contract.storage_balance_of(accounts(0)).is_some());
+    }
+
+    #[test]
+    fn test_unpause() {
+        let (mut contract, room_id) = paused_contract();
+
+        contract.unpause();
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The app is paused")]
+    fn test_join_app_paused() {
+        let (mut contract, room_id) = app_paused_contract();
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The app is paused")]
+    fn test_close_app_paused() {
+        let (mut contract, room_id) = app_paused_contract();
+
+        set_context(&accounts(0), 0);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_app_pause_keeps_other_apps() {
+        let (mut contract, _) = app_paused_contract();
+        register_app(&mut contract, &accounts(4), "other_app");
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(room_config("other_app", 3));
+    }
+
+    #[test]
+    fn test_contract_owner_pauses_app() {
+        let mut contract = owned_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        set_context(&owner_id(), 0);
+        contract.pause_app(APP_NAME.to_string());
+        assert!(contract.get_app(APP_NAME.to_string()).unwrap().is_paused);
+
+        contract.unpause_app(APP_NAME.to_string());
+        assert!(!contract.get_app(APP_NAME.to_string()).unwrap().is_paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the app owner or the contract owner can pause the app")]
+    fn test_pause_app_not_owner() {
+        let mut contract = owned_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        set_context(&accounts(0), 0);
+        contract.pause_app(APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_emergency_close_room() {
+        let (mut contract, _) = paused_contract();
+
+        let mut config = room_config(APP_NAME, 3);
+        config.entry_fee = Some(U128(ONE_NEAR));
+        contract.unpause();
+        set_context(&accounts(1), DEPOSIT + ONE_NEAR);
+        let room_id = contract.create_room(config);
+        contract.start_game(room_id, APP_NAME.to_string());
+
+        set_context(&owner_id(), 0);
+        contract.pause();
+        contract.emergency_close_room(room_id, "abuse".to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert_eq!(transferred_to(&accounts(1)), ONE_NEAR);
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(1))
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can call the method")]
+    fn test_emergency_close_room_not_owner() {
+        let mut contract = owned_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(5), 0);
+        contract.emergency_close_room(room_id, "abuse".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The app registration is closed")]
+    fn test_register_app_closed() {
+        let mut contract = owned_contract();
+        contract.update_config(ContractConfig {
+            is_app_registration_open: false,
+        });
+
+        register_app(&mut contract, &accounts(5), APP_NAME);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::*;
+use crate::attestation::ResultAttestationConfig;
+use crate::expiry::DEFAULT_ROOM_TTL;
+use crate::matchmaking::MAX_QUEUE_SIZE;
+use crate::room_state::RoomStateConfig;
+use crate::turns::TurnConfig;
+use near_sdk::json_types::U64;
+use near_sdk::{env, require, BlockHeight};
+
+const MAX_APP_NAME_LENGTH: usize = 64;
+const DEFAULT_QUEUE_TTL_BLOCKS: BlockHeight = 600;
+const DEFAULT_RATING_WINDOW: u32 = 100;
+pub const DEFAULT_MAX_ROOMS_PER_ACCOUNT: u32 = 1;
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AppMetadata {
+    pub title: String,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct App {
+    pub owner_id: AccountId,
+    pub metadata: AppMetadata,
+    pub default_player_limit: usize,
+    pub max_player_limit: usize,
+    pub allow_hidden_rooms: bool,
+    /// A hint for the clients about the format of the `extra` field of the rooms.
+    pub extra_schema: Option<String>,
+    /// The number of blocks after which the matchmaking queue entry expires.
+    pub queue_ttl_blocks: BlockHeight,
+    /// The max number of the accounts in the matchmaking queue of the app.
+    pub max_queue_size: u32,
+    /// The max difference between the ratings of the matched players.
+    pub rating_window: u32,
+    /// The account that can report the results of the rooms besides the room owners.
+    pub referee_id: Option<AccountId>,
+    /// The players agree on the room results when set.
+    pub result_attestation: Option<ResultAttestationConfig>,
+    /// The tokens that can be used for the entry fees of the rooms.
+    pub accepted_tokens: Vec<AccountId>,
+    /// The nanoseconds without activity after which the room can be removed by anyone.
+    pub room_ttl: U64,
+    /// The share of the storage freed by the expired room in percent, which is paid to the
+    /// account that removes it.
+    pub cleanup_bounty_percent: u32,
+    /// The number of rooms of the app an account can opt in to be in at once.
+    pub max_rooms_per_account: u32,
+    /// The limits and the write access of the room state, see [`Contract::set_room_state`].
+    pub room_state: RoomStateConfig,
+    /// The limits of the move log and the turn timeout, see [`Contract::submit_move`].
+    pub turns: TurnConfig,
+    /// The rooms of the app are frozen when set, see [`Contract::pause_app`].
+    pub is_paused: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AppConfig {
+    pub metadata: AppMetadata,
+    pub default_player_limit: usize,
+    pub max_player_limit: usize,
+    pub allow_hidden_rooms: bool,
+    pub extra_schema: Option<String>,
+    pub queue_ttl_blocks: Option<BlockHeight>,
+    pub max_queue_size: Option<u32>,
+    pub rating_window: Option<u32>,
+    pub referee_id: Option<AccountId>,
+    pub result_attestation: Option<ResultAttestationConfig>,
+    pub accepted_tokens: Option<Vec<AccountId>>,
+    pub room_ttl: Option<U64>,
+    pub cleanup_bounty_percent: Option<u32>,
+    pub max_rooms_per_account: Option<u32>,
+    pub room_state: Option<RoomStateConfig>,
+    pub turns: Option<TurnConfig>,
+}
+
+impl AppConfig {
+    fn assert_valid(&self) {
+        require!(
+            self.default_player_limit > 0,
+            "The default player limit must be positive"
+        );
+        require!(
+            self.default_player_limit <= self.max_player_limit,
+            "The default player limit exceeds the max player limit"
+        );
+        require!(
+            self.max_queue_size.unwrap_or(MAX_QUEUE_SIZE) <= MAX_QUEUE_SIZE,
+            "The max queue size exceeds the limit of the contract"
+        );
+        require!(
+            self.cleanup_bounty_percent.unwrap_or(0) <= 100,
+            "The cleanup bounty can't exceed 100 percent"
+        );
+        require!(
+            self.max_rooms_per_account != Some(0),
+            "The max rooms per account must be positive"
+        );
+        if let Some(result_attestation) = &self.result_attestation {
+            result_attestation.assert_valid();
+        }
+        if let Some(room_state) = &self.room_state {
+            room_state.assert_valid();
+        }
+        if let Some(turns) = &self.turns {
+            turns.assert_valid();
+        }
+    }
+}
+
+impl App {
+    fn new(owner_id: AccountId, app_config: AppConfig) -> Self {
+        app_config.assert_valid();
+        Self {
+            owner_id,
+            metadata: app_config.metadata,
+            default_player_limit: app_config.default_player_limit,
+            max_player_limit: app_config.max_player_limit,
+            allow_hidden_rooms: app_config.allow_hidden_rooms,
+            extra_schema: app_config.extra_schema,
+            queue_ttl_blocks: app_config
+                .queue_ttl_blocks
+                .unwrap_or(DEFAULT_QUEUE_TTL_BLOCKS),
+            max_queue_size: app_config.max_queue_size.unwrap_or(MAX_QUEUE_SIZE),
+            rating_window: app_config.rating_window.unwrap_or(DEFAULT_RATING_WINDOW),
+            referee_id: app_config.referee_id,
+            result_attestation: app_config.result_attestation,
+            accepted_tokens: app_config.accepted_tokens.unwrap_or_default(),
+            room_ttl: app_config.room_ttl.unwrap_or(U64(DEFAULT_ROOM_TTL)),
+            cleanup_bounty_percent: app_config.cleanup_bounty_percent.unwrap_or(0),
+            max_rooms_per_account: app_config
+                .max_rooms_per_account
+                .unwrap_or(DEFAULT_MAX_ROOMS_PER_ACCOUNT),
+            room_state: app_config.room_state.unwrap_or_default(),
+            turns: app_config.turns.unwrap_or_default(),
+            is_paused: false,
+        }
+    }
+
+    fn update(&mut self, app_config: AppConfig) {
+        let is_paused = self.is_paused;
+        *self = Self::new(self.owner_id.clone(), app_config);
+        self.is_paused = is_paused;
+    }
+
+    /// Returns the player limit for the new room, falling back to the app default.
+    pub fn room_player_limit(&self, player_limit: Option<usize>) -> usize {
+        let player_limit = player_limit.unwrap_or(self.default_player_limit);
+        require!(player_limit > 0, "The player limit must be positive");
+        require!(
+            player_limit <= self.max_player_limit,
+            "The player limit exceeds the max player limit of the app"
+        );
+        player_limit
+    }
+}
+
+impl Contract {
+    pub fn internal_get_app(&self, app_name: &AppName) -> &App {
+        self.apps.get(app_name).expect("App not found")
+    }
+
+    fn assert_app_owner(&self, app_name: &AppName) {
+        require!(
+            self.internal_get_app(app_name).owner_id == predecessor_account_id(),
+            "Only the app owner can update the app"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers a new app owned by the caller and creates the app collections.
+    #[payable]
+    pub fn register_app(&mut self, app_name: AppName, app_config: AppConfig) {
+        require!(
+            !app_name.is_empty() && app_name.len() <= MAX_APP_NAME_LENGTH,
+            "Invalid app name"
+        );
+        require!(!self.apps.contains_key(&app_name), "App already registered");
+        self.assert_not_paused();
+
+        let account_id = predecessor_account_id();
+        if !self.config.is_app_registration_open && account_id != self.owner_id {
+            panic!("The app registration is closed")
+        }
+        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_deposit());
+        account.start_storage_tracker();
+
+        self.apps
+            .insert(app_name.clone(), App::new(account_id.clone(), app_config));
+
+        // The prefixes of the app collections are derived from the app name only.
+        let hash = env::sha256_array(app_name.as_bytes());
+        self.available_rooms_per_app
+            .insert(app_name.clone(), UnorderedSet::new(AppRooms { hash }));
+        self.rooms_per_app
+            .insert(app_name.clone(), UnorderedSet::new(AppRoomIds { hash }));
+        self.rooms_by_fill
+            .insert(app_name.clone(), TreeMap::new(AppRoomsByFill { hash }));
+        self.ratings
+            .insert(app_name.clone(), UnorderedMap::new(AppRatings { hash }));
+        self.app_bans
+            .insert(app_name.clone(), UnorderedMap::new(AppBanList { hash }));
+        self.leaderboards.insert(app_name.clone(), Vec::new());
+        self.app_queues.insert(app_name, Vec::new());
+
+        self.apps.flush();
+        self.available_rooms_per_app.flush();
+        self.rooms_per_app.flush();
+        self.rooms_by_fill.flush();
+        self.ratings.flush();
+        self.leaderboards.flush();
+        self.app_bans.flush();
+        self.app_queues.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&account_id, account);
+    }
+
+    #[payable]
+    pub fn update_app(&mut self, app_name: AppName, app_config: AppConfig) {
+        self.assert_not_paused();
+        self.assert_app_owner(&app_name);
+
+        let account_id = predecessor_account_id();
+        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_deposit());
+        account.start_storage_tracker();
+
+        self.apps.get_mut(&app_name).unwrap().update(app_config);
+
+        self.apps.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&account_id, account);
+    }
+
+    pub fn get_app(&self, app_name: AppName) -> Option<App> {
+        self.apps.get(&app_name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+
+    #[test]
+    fn test_register_app() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        let app = contract.get_app(APP_NAME.to_string()).unwrap();
+        assert_eq!(app.owner_id, accounts(0));
+        assert_eq!(app.max_player_limit, MAX_PLAYER_LIMIT);
+        assert!(contract.internal_get_account(&accounts(0)).used_bytes > 0);
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "App already registered")]
+    fn test_register_app_twice() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+        register_app(&mut contract, &accounts(1), APP_NAME);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account that pays for the storage outside")]
+    fn test_storage_unregister_force_of_app_owner() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        set_context(&accounts(0), 1);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "The default player limit exceeds the max player limit")]
+    fn test_register_app_invalid_player_limits() {
+        let mut contract = new_contract();
+        let mut config = app_config();
+        config.default_player_limit = config.max_player_limit + 1;
+
+        set_context(&accounts(0), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), config);
+    }
+
+    #[test]
+    fn test_update_app() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        let mut config = app_config();
+        config.allow_hidden_rooms = false;
+        config.extra_schema = Some("{}".to_string());
+        set_context(&accounts(0), 0);
+        contract.update_app(APP_NAME.to_string(), config);
+
+        let app = contract.get_app(APP_NAME.to_string()).unwrap();
+        assert!(!app.allow_hidden_rooms);
+        assert_eq!(app.extra_schema, Some("{}".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the app owner can update the app")]
+    fn test_update_app_not_owner() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        set_context(&accounts(1), 0);
+        contract.update_app(APP_NAME.to_string(), app_config());
+    }
+
+    #[test]
+    #[should_panic(expected = "App not found")]
+    fn test_create_room_in_unregistered_app() {
+        let mut contract = new_contract();
+
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+    }
+
+    #[test]
+    fn test_create_room_uses_default_player_limit() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        let mut config = room_config(APP_NAME, 2);
+        config.player_limit = None;
+        set_context(&accounts(1), DEPOSIT);
+        let room_id = contract.create_room(config);
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.player_limit, DEFAULT_PLAYER_LIMIT);
+    }
+
+    #[test]
+    #[should_panic(expected = "The player limit exceeds the max player limit of the app")]
+    fn test_create_room_exceeding_max_player_limit() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, MAX_PLAYER_LIMIT + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Hidden rooms are not allowed in the app")]
+    fn test_create_hidden_room_not_allowed() {
+        let mut contract = new_contract();
+        let mut config = app_config();
+        config.allow_hidden_rooms = false;
+        set_context(&accounts(0), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), config);
+
+        let mut config = room_config(APP_NAME, 2);
+        config.is_hidden = true;
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(config);
+    }
+
+    #[test]
+    #[should_panic(expected = "The room doesn't belong to the app")]
+    fn test_join_room_of_another_app() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(0), APP_NAME);
+        register_app(&mut contract, &accounts(0), "other_app");
+
+        set_context(&accounts(1), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(room_id, "other_app".to_string(), None);
+    }
+}
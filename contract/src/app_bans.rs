@@ -0,0 +1,270 @@
+use crate::*;
+use near_sdk::{env, require, BlockHeight};
+
+const MAX_BAN_REASON_LENGTH: usize = 256;
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AppBan {
+    pub account_id: AccountId,
+    pub reason: Option<String>,
+    pub banned_at: BlockHeight,
+}
+
+impl Contract {
+    /// Returns the error if the account is banned in the app.
+    pub(crate) fn check_not_app_banned(
+        &self,
+        app_name: &AppName,
+        account_id: &AccountId,
+    ) -> Result<(), String> {
+        let is_banned = self
+            .app_bans
+            .get(app_name)
+            .expect("App not found")
+            .contains_key(account_id);
+        if is_banned {
+            return Err("The account is banned in the app".to_string());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn assert_not_app_banned(&self, app_name: &AppName, account_id: &AccountId) {
+        if let Err(err) = self.check_not_app_banned(app_name, account_id) {
+            panic!("{}", err)
+        }
+    }
+
+    fn assert_can_manage_app_bans(&self, app_name: &AppName) {
+        if self.internal_get_app(app_name).owner_id != predecessor_account_id() {
+            panic!("Only the app owner can manage the app bans")
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Bans the account in every room of the app: it can't create, join the rooms or enqueue for
+    /// the matchmaking. The rooms the account is already in are not affected. The app owner pays
+    /// for the ban storage.
+    #[payable]
+    pub fn app_ban(&mut self, app_name: AppName, account_id: AccountId, reason: Option<String>) {
+        self.assert_not_paused();
+        self.assert_can_manage_app_bans(&app_name);
+        require!(
+            reason
+                .as_ref()
+                .is_none_or(|reason| reason.len() <= MAX_BAN_REASON_LENGTH),
+            "The ban reason is too long"
+        );
+
+        let owner_id = predecessor_account_id();
+        let mut account = self.internal_unwrap_account_or_create(&owner_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let app_bans = self.app_bans.get_mut(&app_name).unwrap();
+        app_bans.insert(
+            account_id.clone(),
+            AppBan {
+                account_id: account_id.clone(),
+                reason,
+                banned_at: env::block_height(),
+            },
+        );
+        app_bans.flush();
+        self.app_bans.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&owner_id, account);
+
+        let app_ban = self
+            .app_bans
+            .get(&app_name)
+            .unwrap()
+            .get(&account_id)
+            .unwrap();
+        events::emit_app_banned(&app_name, app_ban);
+    }
+
+    pub fn app_unban(&mut self, app_name: AppName, account_id: AccountId) {
+        self.assert_not_paused();
+        self.assert_can_manage_app_bans(&app_name);
+
+        let owner_id = predecessor_account_id();
+        let mut account = self.internal_get_account(&owner_id);
+        account.start_storage_tracker();
+
+        let app_bans = self.app_bans.get_mut(&app_name).unwrap();
+        let app_ban = app_bans
+            .remove(&account_id)
+            .expect("The account is not banned in the app");
+        app_bans.flush();
+        self.app_bans.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&owner_id, account);
+
+        events::emit_app_unbanned(&app_name, &app_ban);
+    }
+
+    pub fn get_app_bans(
+        &self,
+        app_name: AppName,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<AppBan> {
+        let app_bans = self.app_bans.get(&app_name).expect("App not found");
+        let start = from_index.map(|from_index| from_index.0).unwrap_or(0);
+
+        app_bans
+            .values()
+            .skip(start as usize)
+            .take(limit.unwrap_or(app_bans.len() as u64) as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    fn setup_app(contract: &mut Contract, banned_id: AccountId) {
+        register_app(contract, &accounts(5), APP_NAME);
+        set_context(&accounts(5), 0);
+        contract.app_ban(
+            APP_NAME.to_string(),
+            banned_id,
+            Some("cheating".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_app_ban() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        let owner_used_bytes = used_bytes(&contract, &accounts(5));
+
+        set_context(&accounts(5), 0);
+        contract.app_ban(
+            APP_NAME.to_string(),
+            accounts(1),
+            Some("cheating".to_string()),
+        );
+        let banned_used_bytes = used_bytes(&contract, &accounts(5));
+        assert!(banned_used_bytes > owner_used_bytes);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"app_banned","data":[{"app_name":"app","account_id":"bob","reason":"cheating","moderator_id":"fargo"}]}"#
+            ]
+        );
+
+        let app_bans = contract.get_app_bans(APP_NAME.to_string(), None, None);
+        assert_eq!(app_bans.len(), 1);
+        assert_eq!(app_bans[0].account_id, accounts(1));
+        assert_eq!(app_bans[0].reason, Some("cheating".to_string()));
+
+        contract.app_unban(APP_NAME.to_string(), accounts(1));
+        assert!(contract
+            .get_app_bans(APP_NAME.to_string(), None, None)
+            .is_empty());
+        assert!(used_bytes(&contract, &accounts(5)) < banned_used_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the app owner can manage the app bans")]
+    fn test_app_ban_not_app_owner() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        set_context(&accounts(0), 0);
+        contract.app_ban(APP_NAME.to_string(), accounts(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not banned in the app")]
+    fn test_app_unban_not_banned() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        set_context(&accounts(5), 0);
+        contract.app_unban(APP_NAME.to_string(), accounts(1));
+    }
+
+    #[test]
+    fn test_get_app_bans_paginated() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(5), 0);
+        for idx in 0..4 {
+            contract.app_ban(APP_NAME.to_string(), accounts(idx), None);
+        }
+
+        let app_bans = contract.get_app_bans(APP_NAME.to_string(), Some(U128(1)), Some(2));
+        let account_ids: Vec<AccountId> = app_bans
+            .into_iter()
+            .map(|app_ban| app_ban.account_id)
+            .collect();
+        assert_eq!(account_ids, vec![accounts(1), accounts(2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is banned in the app")]
+    fn test_create_room_app_banned() {
+        let mut contract = new_contract();
+        setup_app(&mut contract, accounts(0));
+
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is banned in the app")]
+    fn test_join_app_banned() {
+        let mut contract = new_contract();
+        setup_app(&mut contract, accounts(1));
+        set_context(&accounts(0), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is banned in the app")]
+    fn test_random_join_app_banned() {
+        let mut contract = new_contract();
+        setup_app(&mut contract, accounts(1));
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.random_join(APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is banned in the app")]
+    fn test_enqueue_app_banned() {
+        let mut contract = new_contract();
+        setup_app(&mut contract, accounts(1));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.enqueue(APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_app_banned_is_dropped_from_queue() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        for idx in 0..3 {
+            set_context(&accounts(idx), DEPOSIT);
+            contract.enqueue(APP_NAME.to_string(), None);
+        }
+
+        set_context(&accounts(5), 0);
+        contract.app_ban(APP_NAME.to_string(), accounts(0), None);
+        contract.match_players(APP_NAME.to_string(), 10);
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+    }
+}
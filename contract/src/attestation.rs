@@ -0,0 +1,482 @@
+use crate::*;
+use crate::lifecycle::RoomStatus;
+use near_sdk::{env, require, BlockHeight};
+
+/// Enables the result attestation mode of the app. The room result is agreed by the players
+/// instead of being reported by the room owner alone.
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ResultAttestationConfig {
+    /// The share of the room players, in percent, that must submit the same result hash.
+    pub quorum_percent: u32,
+    /// The number of blocks after the first submission during which the result can be disputed.
+    pub dispute_window_blocks: BlockHeight,
+}
+
+impl ResultAttestationConfig {
+    pub fn assert_valid(&self) {
+        require!(
+            self.quorum_percent > 0 && self.quorum_percent <= 100,
+            "The quorum must be between 1 and 100 percent"
+        );
+    }
+
+    /// Returns the number of the matching submissions required to finalize the result.
+    fn quorum(&self, number_of_players: usize) -> usize {
+        let quorum = (number_of_players * self.quorum_percent as usize).div_ceil(100);
+        quorum.max(1)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    Playing,
+    AwaitingResults { since: BlockHeight },
+    Finalized { result_hash: Base58CryptoHash },
+    Disputed,
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ResultAttestation {
+    pub account_id: AccountId,
+    pub result_hash: Base58CryptoHash,
+}
+
+/// Returns the hash of the ranking that the players attest: the sha256 of the comma separated
+/// account ids, from the winner to the loser.
+pub fn ranking_hash(ranking: &[AccountId]) -> Base58CryptoHash {
+    let ranking: Vec<&str> = ranking
+        .iter()
+        .map(|account_id| account_id.as_str())
+        .collect();
+    env::sha256_array(ranking.join(",").as_bytes()).into()
+}
+
+impl Room {
+    /// Returns the result hash that reached the quorum of the room players.
+    fn agreed_result_hash(&self, config: &ResultAttestationConfig) -> Option<Base58CryptoHash> {
+        let quorum = config.quorum(self.players.len());
+        self.attestations
+            .iter()
+            .map(|attestation| attestation.result_hash)
+            .find(|result_hash| {
+                self.attestations
+                    .iter()
+                    .filter(|attestation| attestation.result_hash.eq(result_hash))
+                    .count()
+                    >= quorum
+            })
+    }
+
+    fn is_dispute_window_over(&self, config: &ResultAttestationConfig) -> bool {
+        match self.result_status {
+            ResultStatus::AwaitingResults { since } => {
+                since + config.dispute_window_blocks <= env::block_height()
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the result submitted by the player, e.g. when the player leaves the room.
+    pub fn remove_attestation(&mut self, account_id: &AccountId) {
+        self.attestations
+            .retain(|attestation| attestation.account_id.ne(account_id));
+    }
+}
+
+impl Contract {
+    fn internal_get_attestation_config(&self, app_name: &AppName) -> ResultAttestationConfig {
+        self.internal_get_app(app_name)
+            .result_attestation
+            .clone()
+            .expect("The result attestation is not enabled in the app")
+    }
+
+    /// Moves the room to the new result status. The room owner pays for the status storage,
+    /// same as for the rest of the room record.
+    fn internal_set_result_status(&mut self, room_id: &RoomId, result_status: ResultStatus) {
+        let owner_id = self.rooms.get(room_id).unwrap().owner_id.clone();
+        let mut account = self.internal_get_account(&owner_id);
+        account.start_storage_tracker();
+
+        let room = self.rooms.get_mut(room_id).unwrap();
+        room.result_status = result_status;
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&owner_id, account);
+
+        let room = self.rooms.get(room_id).unwrap();
+        match room.result_status {
+            ResultStatus::Finalized { .. } => events::emit_result_finalized(room),
+            ResultStatus::Disputed => events::emit_result_disputed(room),
+            _ => {}
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Submits the hash of the room result on behalf of the player, see [`ranking_hash`].
+    /// The result is finalized as soon as the quorum of the players submits the same hash.
+    /// Accepts the `Finished` rooms.
+    #[payable]
+    pub fn submit_result(&mut self, room_id: RoomId, result_hash: Base58CryptoHash) {
+        self.assert_room_not_paused(&room_id);
+        let player_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let config = self.internal_get_attestation_config(&room.app_name);
+
+        if !room.players.contains(&player_id) {
+            panic!("Only the players can submit the result")
+        }
+        room.assert_status(&[RoomStatus::Finished]);
+        match room.result_status {
+            ResultStatus::Playing => {}
+            ResultStatus::AwaitingResults { .. } if !room.is_dispute_window_over(&config) => {}
+            _ => panic!("The result can't be submitted anymore"),
+        }
+        if room
+            .attestations
+            .iter()
+            .any(|attestation| attestation.account_id.eq(&player_id))
+        {
+            panic!("The result is already submitted")
+        }
+
+        let mut account = self.internal_unwrap_account_or_create(&player_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.attestations.push(ResultAttestation {
+            account_id: player_id.clone(),
+            result_hash,
+        });
+        let is_first_attestation = room.result_status == ResultStatus::Playing;
+        let agreed_result_hash = room.agreed_result_hash(&config);
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&player_id, account);
+
+        if is_first_attestation {
+            let since = env::block_height();
+            self.internal_set_result_status(&room_id, ResultStatus::AwaitingResults { since });
+        }
+        if let Some(result_hash) = agreed_result_hash {
+            self.internal_set_result_status(&room_id, ResultStatus::Finalized { result_hash });
+        }
+    }
+
+    /// Flags the submitted result for the app owner to resolve. Can be called by any player
+    /// before the dispute window is over.
+    pub fn dispute_result(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        let player_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let config = self.internal_get_attestation_config(&room.app_name);
+
+        if !room.players.contains(&player_id) {
+            panic!("Only the players can dispute the result")
+        }
+        if !matches!(room.result_status, ResultStatus::AwaitingResults { .. })
+            || room.is_dispute_window_over(&config)
+        {
+            panic!("The result can't be disputed")
+        }
+
+        self.internal_set_result_status(&room_id, ResultStatus::Disputed);
+    }
+
+    /// Finalizes the undisputed result once the dispute window is over. Anyone can call it.
+    /// The conflicting submissions without the quorum are flagged as disputed instead.
+    pub fn finalize_result(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let config = self.internal_get_attestation_config(&room.app_name);
+
+        if !room.is_dispute_window_over(&config) {
+            panic!("The dispute window is not over")
+        }
+
+        let result_hash = match room.attestations.first() {
+            Some(attestation) => attestation.result_hash,
+            None => panic!("There are no submitted results, since the players have left"),
+        };
+        let result_status = if room
+            .attestations
+            .iter()
+            .all(|attestation| attestation.result_hash == result_hash)
+        {
+            ResultStatus::Finalized { result_hash }
+        } else {
+            ResultStatus::Disputed
+        };
+
+        self.internal_set_result_status(&room_id, result_status);
+    }
+
+    /// Resolves the disputed result. Only the app owner can call it.
+    pub fn resolve_result(&mut self, room_id: RoomId, result_hash: Base58CryptoHash) {
+        self.assert_room_not_paused(&room_id);
+        let owner_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+
+        if self.internal_get_app(&room.app_name).owner_id.ne(&owner_id) {
+            panic!("Only the app owner can resolve the result")
+        }
+        if room.result_status != ResultStatus::Disputed {
+            panic!("The result is not disputed")
+        }
+
+        self.internal_set_result_status(&room_id, ResultStatus::Finalized { result_hash });
+    }
+
+    pub fn get_result_status(&self, room_id: RoomId) -> ResultStatus {
+        self.rooms
+            .get(&room_id)
+            .expect("Room id not found")
+            .result_status
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    const DISPUTE_WINDOW_BLOCKS: BlockHeight = 10;
+
+    fn setup_attested_room(contract: &mut Contract, quorum_percent: u32) -> RoomId {
+        let mut config = app_config();
+        config.result_attestation = Some(ResultAttestationConfig {
+            quorum_percent,
+            dispute_window_blocks: DISPUTE_WINDOW_BLOCKS,
+        });
+        let room_id = setup_app_room(contract, config, room_config(APP_NAME, 3), 3);
+        finish_game(contract, &accounts(0), room_id);
+        room_id
+    }
+
+    fn submit(contract: &mut Contract, room_id: RoomId, account_id: AccountId, block_height: u64) {
+        set_context_at_block(&account_id, DEPOSIT, block_height);
+        contract.submit_result(room_id, winner_hash());
+    }
+
+    fn winner_hash() -> Base58CryptoHash {
+        ranking_hash(&[accounts(0), accounts(1), accounts(2)])
+    }
+
+    #[test]
+    fn test_result_finalized_by_quorum() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 60);
+
+        submit(&mut contract, room_id, accounts(0), 1);
+        assert_eq!(
+            contract.get_result_status(room_id),
+            ResultStatus::AwaitingResults { since: 1 }
+        );
+
+        submit(&mut contract, room_id, accounts(1), 2);
+        assert_eq!(
+            contract.get_result_status(room_id),
+            ResultStatus::Finalized {
+                result_hash: winner_hash()
+            }
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"result_finalized","data":[{"room_id":0,"app_name":"app","owner_id":"alice"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_result_finalized_after_dispute_window() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(4), DEPOSIT, 1 + DISPUTE_WINDOW_BLOCKS);
+        contract.finalize_result(room_id);
+        assert_eq!(
+            contract.get_result_status(room_id),
+            ResultStatus::Finalized {
+                result_hash: winner_hash()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The dispute window is not over")]
+    fn test_finalize_result_during_dispute_window() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(4), DEPOSIT, DISPUTE_WINDOW_BLOCKS);
+        contract.finalize_result(room_id);
+    }
+
+    #[test]
+    fn test_conflicting_results_are_disputed() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(1), DEPOSIT, 2);
+        contract.submit_result(
+            room_id,
+            ranking_hash(&[accounts(1), accounts(0), accounts(2)]),
+        );
+
+        set_context_at_block(&accounts(4), DEPOSIT, 1 + DISPUTE_WINDOW_BLOCKS);
+        contract.finalize_result(room_id);
+        assert_eq!(contract.get_result_status(room_id), ResultStatus::Disputed);
+    }
+
+    #[test]
+    fn test_dispute_resolved_by_app_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(2), DEPOSIT, 2);
+        contract.dispute_result(room_id);
+        assert_eq!(contract.get_result_status(room_id), ResultStatus::Disputed);
+
+        set_context(&accounts(5), DEPOSIT);
+        contract.resolve_result(room_id, winner_hash());
+        assert_eq!(
+            contract.get_result_status(room_id),
+            ResultStatus::Finalized {
+                result_hash: winner_hash()
+            }
+        );
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(1), accounts(2)]);
+        assert!(contract
+            .get_rating(APP_NAME.to_string(), accounts(0))
+            .is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "The result can't be disputed")]
+    fn test_dispute_after_dispute_window() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(2), DEPOSIT, 1 + DISPUTE_WINDOW_BLOCKS);
+        contract.dispute_result(room_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the app owner can resolve the result")]
+    fn test_resolve_result_not_app_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context_at_block(&accounts(2), DEPOSIT, 2);
+        contract.dispute_result(room_id);
+        contract.resolve_result(room_id, winner_hash());
+    }
+
+    #[test]
+    #[should_panic(expected = "The result is already submitted")]
+    fn test_submit_result_twice() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+        submit(&mut contract, room_id, accounts(0), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the players can submit the result")]
+    fn test_submit_result_not_player() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(3), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The ranking doesn't match the agreed result")]
+    fn test_report_result_not_matching_agreed_result() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 50);
+        submit(&mut contract, room_id, accounts(0), 1);
+        submit(&mut contract, room_id, accounts(1), 2);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0), accounts(2)]);
+    }
+
+    #[test]
+    fn test_leave_refunds_attestation() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+
+        submit(&mut contract, room_id, accounts(1), 1);
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+
+        assert_eq!(contract.internal_get_account(&accounts(1)).used_bytes, 0);
+        assert!(contract
+            .rooms
+            .get(&room_id)
+            .unwrap()
+            .attestations
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "There are no submitted results, since the players have left")]
+    fn test_finalize_result_after_submitter_left() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(1), 1);
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+
+        set_context_at_block(&accounts(4), DEPOSIT, 1 + DISPUTE_WINDOW_BLOCKS);
+        contract.finalize_result(room_id);
+    }
+
+    #[test]
+    fn test_report_finalized_result_by_anyone() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 60);
+        submit(&mut contract, room_id, accounts(0), 1);
+        submit(&mut contract, room_id, accounts(1), 2);
+
+        set_context(&accounts(3), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(1), accounts(2)]);
+        assert_eq!(
+            contract
+                .get_rating(APP_NAME.to_string(), accounts(0))
+                .unwrap()
+                .rating,
+            1516
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or the referee can report the result")]
+    fn test_report_not_finalized_result_by_stranger() {
+        let mut contract = new_contract();
+        let room_id = setup_attested_room(&mut contract, 100);
+        submit(&mut contract, room_id, accounts(0), 1);
+
+        set_context(&accounts(3), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(1), accounts(2)]);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::*;
+use crate::roles::Permission;
+use near_sdk::{env, BlockHeight};
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Ban {
+    pub account_id: AccountId,
+    /// The block height when the ban is lifted. The ban is permanent when it's not set.
+    pub expires_at: Option<BlockHeight>,
+}
+
+impl Ban {
+    fn is_expired(&self, block_height: BlockHeight) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= block_height)
+    }
+}
+
+impl Room {
+    pub fn is_banned(&self, account_id: &AccountId) -> bool {
+        let block_height = env::block_height();
+        self.bans
+            .iter()
+            .any(|ban| ban.account_id.eq(account_id) && !ban.is_expired(block_height))
+    }
+
+    /// Drops the expired bans. The bans are cleaned up lazily, whenever they change.
+    fn remove_expired_bans(&mut self) {
+        let block_height = env::block_height();
+        self.bans.retain(|ban| !ban.is_expired(block_height));
+    }
+}
+
+impl Contract {
+    /// Removes the player from the room and from the rooms of their account. The player gets
+    /// back the storage of their slot and the entry fee. Accepts the `Lobby` and `Starting`
+    /// rooms.
+    fn internal_kick(&mut self, room_id: &RoomId, player_id: &AccountId) {
+        let room = self.rooms.get(room_id).unwrap();
+        room.assert_status(&[RoomStatus::Lobby, RoomStatus::Starting]);
+        if !room.players.contains(player_id) {
+            panic!("The account is not a player in the room")
+        }
+
+        self.internal_remove_player_with_refund(room_id, player_id);
+        self.internal_refund_entry_fee(room_id, player_id);
+
+        events::emit_player_kicked(self.rooms.get(room_id).unwrap(), player_id);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Removes the player from the room. The player can join again. Accepts the `Lobby` and
+    /// `Starting` rooms.
+    pub fn kick(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let moderator_id = predecessor_account_id();
+        room.assert_permission(&moderator_id, Permission::Kick);
+        room.assert_outranks(&moderator_id, &account_id);
+
+        self.internal_kick(&room_id, &account_id);
+    }
+
+    /// Bans the account from the room for the number of blocks, or permanently. The player is
+    /// kicked if they are in the room. The ban is paid by the owner even when the account is
+    /// banned by a moderator.
+    pub fn ban(&mut self, room_id: RoomId, account_id: AccountId, duration_blocks: Option<u64>) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let moderator_id = predecessor_account_id();
+        room.assert_permission(&moderator_id, Permission::Ban);
+        room.assert_outranks(&moderator_id, &account_id);
+        if duration_blocks == Some(0) {
+            panic!("The ban duration must be positive")
+        }
+
+        if room.players.contains(&account_id) {
+            self.internal_kick(&room_id, &account_id);
+        }
+
+        let ban = Ban {
+            account_id: account_id.clone(),
+            expires_at: duration_blocks.map(|duration| env::block_height() + duration),
+        };
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.remove_expired_bans();
+            room.bans.retain(|ban| ban.account_id.ne(&account_id));
+            room.bans.push(ban);
+        });
+
+        events::emit_player_banned(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+
+    pub fn unban(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::Ban);
+        if !room.is_banned(&account_id) {
+            panic!("The account is not banned")
+        }
+
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.remove_expired_bans();
+            room.bans.retain(|ban| ban.account_id.ne(&account_id));
+        });
+
+        events::emit_player_unbanned(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+
+    /// Returns the active bans of the room.
+    pub fn get_bans(&self, room_id: RoomId) -> Vec<Ban> {
+        let block_height = env::block_height();
+        self.rooms
+            .get(&room_id)
+            .expect("Room id not found")
+            .bans
+            .iter()
+            .filter(|ban| !ban.is_expired(block_height))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    fn join_at_block(contract: &mut Contract, room_id: RoomId, block_height: BlockHeight) {
+        set_context_at_block(&accounts(1), DEPOSIT, block_height);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    fn banned_accounts(contract: &Contract, room_id: RoomId) -> Vec<AccountId> {
+        contract
+            .get_bans(room_id)
+            .into_iter()
+            .map(|ban| ban.account_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_kick_clears_room_pointer() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context(&accounts(0), 0);
+        contract.kick(room_id, accounts(1));
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().players,
+            vec![accounts(0)]
+        );
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(1))
+            .is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+        assert!(contract.get_bans(room_id).is_empty());
+
+        join_at_block(&mut contract, room_id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not a player in the room")]
+    fn test_kick_not_player() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context(&accounts(0), 0);
+        contract.kick(room_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a moderator can kick the player")]
+    fn test_kick_not_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context(&accounts(1), 0);
+        contract.kick(room_id, accounts(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Player is banned")]
+    fn test_ban_kicks_player() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context(&accounts(0), 0);
+        contract.ban(room_id, accounts(1), None);
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(1))
+            .is_none());
+        assert_eq!(banned_accounts(&contract, room_id), vec![accounts(1)]);
+
+        join_at_block(&mut contract, room_id, 1_000_000);
+    }
+
+    #[test]
+    fn test_timed_ban_expires() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context_at_block(&accounts(0), 0, 100);
+        contract.ban(room_id, accounts(1), Some(10));
+        let bans = contract.get_bans(room_id);
+        assert_eq!(bans[0].expires_at, Some(110));
+
+        set_context_at_block(&accounts(0), 0, 110);
+        assert!(contract.get_bans(room_id).is_empty());
+        join_at_block(&mut contract, room_id, 110);
+    }
+
+    #[test]
+    #[should_panic(expected = "Player is banned")]
+    fn test_join_before_ban_expires() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context_at_block(&accounts(0), 0, 100);
+        contract.ban(room_id, accounts(1), Some(10));
+
+        join_at_block(&mut contract, room_id, 109);
+    }
+
+    #[test]
+    fn test_expired_bans_are_cleaned_up() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context_at_block(&accounts(0), 0, 100);
+        contract.ban(room_id, accounts(1), Some(10));
+        contract.ban(room_id, accounts(2), Some(100));
+
+        set_context_at_block(&accounts(0), 0, 150);
+        contract.ban(room_id, accounts(3), None);
+        let room = contract.rooms.get(&room_id).unwrap();
+        let account_ids: Vec<AccountId> =
+            room.bans.iter().map(|ban| ban.account_id.clone()).collect();
+        assert_eq!(account_ids, vec![accounts(2), accounts(3)]);
+    }
+
+    #[test]
+    fn test_unban() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        set_context(&accounts(0), 0);
+        contract.ban(room_id, accounts(2), None);
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+
+        contract.unban(room_id, accounts(2));
+        assert!(contract.get_bans(room_id).is_empty());
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not banned")]
+    fn test_unban_expired_ban() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context_at_block(&accounts(0), 0, 100);
+        contract.ban(room_id, accounts(2), Some(10));
+
+        set_context_at_block(&accounts(0), 0, 110);
+        contract.unban(room_id, accounts(2));
+    }
+
+    #[test]
+    fn test_ban_again_updates_expiry() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 2);
+
+        set_context_at_block(&accounts(0), 0, 100);
+        contract.ban(room_id, accounts(2), Some(10));
+        contract.ban(room_id, accounts(2), None);
+
+        let bans = contract.get_bans(room_id);
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].expires_at, None);
+    }
+}
@@ -1,22 +1,150 @@
 use crate::*;
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::require;
+
+const MAX_ROOM_TAGS: usize = 8;
+const MAX_TAG_LENGTH: usize = 32;
+const DEFAULT_SEARCH_LIMIT: u64 = 50;
+/// The room ids that one search reads at most, the next search resumes after them.
+const MAX_SEARCH_SCAN: u64 = 500;
+/// The fill ratio of the full room in the fill index.
+const FULL_FILL_RATIO: u64 = 1_000_000;
+
+pub fn assert_valid_tags(tags: &[String]) {
+    require!(tags.len() <= MAX_ROOM_TAGS, "Too many room tags");
+    for (idx, tag) in tags.iter().enumerate() {
+        require!(
+            !tag.is_empty() && tag.len() <= MAX_TAG_LENGTH,
+            "Invalid room tag"
+        );
+        require!(!tags[..idx].contains(tag), "Duplicate room tag");
+    }
+}
+
+/// The conditions the found rooms meet. The unset ones match any room.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomFilter {
+    /// The room has all of the tags.
+    pub tags: Option<Vec<String>>,
+    /// The seats that are neither taken by the players nor reserved by the invites.
+    pub min_free_seats: Option<usize>,
+    pub owner_id: Option<AccountId>,
+    pub min_player_limit: Option<usize>,
+    pub max_player_limit: Option<usize>,
+}
+
+impl RoomFilter {
+    fn matches(&self, room: &Room) -> bool {
+        self.tags
+            .as_ref()
+            .is_none_or(|tags| tags.iter().all(|tag| room.tags.contains(tag)))
+            && self
+                .min_free_seats
+                .is_none_or(|min_free_seats| room.free_seats() >= min_free_seats)
+            && self
+                .owner_id
+                .as_ref()
+                .is_none_or(|owner_id| room.owner_id.eq(owner_id))
+            && self
+                .min_player_limit
+                .is_none_or(|min_player_limit| room.player_limit >= min_player_limit)
+            && self
+                .max_player_limit
+                .is_none_or(|max_player_limit| room.player_limit <= max_player_limit)
+    }
+}
+
+#[derive(
+    Clone, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomSortKey {
+    #[default]
+    RoomId,
+    /// The same order as the room id, since the room ids are given in the order of creation.
+    CreatedAt,
+    /// The share of the taken and reserved seats, the room id breaks the ties.
+    FillRatio,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomSort {
+    pub key: RoomSortKey,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomPage {
+    pub rooms: Vec<Room>,
+    /// Passed to the next search with the same sort to get the next page. Not set once every
+    /// room id is scanned.
+    pub next_cursor: Option<Base64VecU8>,
+}
+
+/// The position of the last room scanned by the search. The next page starts after it, so the
+/// rooms added or removed in the meantime don't shift the pages.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct RoomCursor {
+    key: RoomSortKey,
+    descending: bool,
+    position: (u64, RoomId),
+}
+
+impl Room {
+    fn free_seats(&self) -> usize {
+        self.player_limit
+            .saturating_sub(self.players.len() + self.invited_players.len())
+    }
+
+    /// Returns the share of the taken and reserved seats, the position of the room in the fill
+    /// index of the app.
+    pub(crate) fn fill_ratio(&self) -> u64 {
+        let taken_seats = (self.player_limit - self.free_seats()) as u64;
+        taken_seats * FULL_FILL_RATIO / self.player_limit as u64
+    }
+}
 
-#[near_bindgen]
 impl Contract {
-    pub fn get_app_account_room(&self, app_name: AppName, account_id: AccountId) -> Option<Room> {
-        let wrapped_room_per_account = self
-            .rooms_per_app_account
-            .get(&app_name);
-        if wrapped_room_per_account.is_none() {
-            return None;
-        }
-        let room_per_account = wrapped_room_per_account.unwrap();
+    pub(crate) fn internal_is_available(&self, room_id: &RoomId, app_name: &AppName) -> bool {
+        self.available_rooms_per_app
+            .get(app_name)
+            .expect("App rooms not found")
+            .contains(room_id)
+    }
 
-        match room_per_account.get(&account_id) {
-            None => None,
-            Some(room_id_opt) => {
-                room_id_opt.map(|room_id| self.rooms.get(&room_id).expect("").clone())
-            }
+    /// Moves the available room in the fill index of the app after its players or invites have
+    /// changed. The contract pays for the index, so it's called outside of the storage trackers.
+    pub(crate) fn internal_update_room_fill(&mut self, room_id: &RoomId, previous_fill_ratio: u64) {
+        let room = match self.rooms.get(room_id) {
+            None => return,
+            Some(room) => room,
+        };
+        let fill_ratio = room.fill_ratio();
+        let app_name = room.app_name.clone();
+        if fill_ratio == previous_fill_ratio || !self.internal_is_available(room_id, &app_name) {
+            return;
         }
+
+        let rooms_by_fill = self.rooms_by_fill.get_mut(&app_name).unwrap();
+        rooms_by_fill.remove(&(previous_fill_ratio, *room_id));
+        rooms_by_fill.insert(&(fill_ratio, *room_id), &());
+        self.rooms_by_fill.flush();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the earliest joined room of the account within the app, see
+    /// [`Contract::get_account_rooms`] for all of them.
+    pub fn get_app_account_room(&self, app_name: AppName, account_id: AccountId) -> Option<Room> {
+        self.internal_get_account_room_ids(&app_name, &account_id)
+            .first()
+            .map(|room_id| Room::clone(self.rooms.get(room_id).expect("Room id not found")))
     }
 
     pub fn get_app_rooms(
@@ -29,16 +157,95 @@ impl Contract {
             .available_rooms_per_app
             .get(&app_name)
             .expect("App rooms not found");
-        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let start = usize::try_from(from_index.unwrap_or(U128(0)).0).unwrap_or(usize::MAX);
 
         app_rooms
             .iter()
-            .skip(start as usize)
-            .take(limit.unwrap_or(0))
-            .map(|x| self.rooms.get(x).expect("Room not found").clone())
+            .skip(start)
+            .take(limit.unwrap_or(DEFAULT_SEARCH_LIMIT as usize))
+            .map(|x| Room::clone(self.rooms.get(x).expect("Room not found")))
             .collect()
     }
 
+    /// Returns the page of the available rooms of the app that match the filter, sorted by the
+    /// room id unless the sort is set. Every search scans at most [`MAX_SEARCH_SCAN`] rooms from
+    /// the cursor, so the page can have fewer rooms than the limit while the next cursor is set.
+    pub fn search_rooms(
+        &self,
+        app_name: AppName,
+        filter: Option<RoomFilter>,
+        sort: Option<RoomSort>,
+        cursor: Option<Base64VecU8>,
+        limit: Option<u64>,
+    ) -> RoomPage {
+        let filter = filter.unwrap_or_default();
+        let sort = sort.unwrap_or_default();
+        let after = cursor.map(|cursor| {
+            let cursor = RoomCursor::try_from_slice(&cursor.0).expect("Invalid cursor");
+            require!(
+                cursor.key == sort.key && cursor.descending == sort.descending,
+                "The cursor doesn't match the sort"
+            );
+            cursor.position
+        });
+        let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT) as usize;
+        require!(limit > 0, "The limit must be positive");
+        let app_rooms = self
+            .available_rooms_per_app
+            .get(&app_name)
+            .expect("App rooms not found");
+        let rooms_by_fill = self.rooms_by_fill.get(&app_name).unwrap();
+
+        // The rooms are scanned by the fill index or by the room ids, which are given in the order
+        // of creation.
+        let mut positions: Box<dyn Iterator<Item = (u64, RoomId)>> = match sort.key {
+            RoomSortKey::FillRatio => match (sort.descending, after) {
+                (false, None) => Box::new(rooms_by_fill.iter().map(|(x, _)| x)),
+                (false, Some(after)) => Box::new(rooms_by_fill.iter_from(after).map(|(x, _)| x)),
+                (true, None) => Box::new(rooms_by_fill.iter_rev().map(|(x, _)| x)),
+                (true, Some(after)) => Box::new(rooms_by_fill.iter_rev_from(after).map(|(x, _)| x)),
+            },
+            RoomSortKey::RoomId | RoomSortKey::CreatedAt => {
+                let room_ids: Box<dyn Iterator<Item = RoomId>> = if sort.descending {
+                    Box::new((0..after.map_or(self.next_room_id, |(_, x)| x)).rev())
+                } else {
+                    Box::new(after.map_or(0, |(_, x)| x + 1)..self.next_room_id)
+                };
+                Box::new(room_ids.map(|room_id| (room_id, room_id)))
+            }
+        };
+
+        let mut rooms = vec![];
+        let mut last_position = None;
+        for position in positions.by_ref().take(MAX_SEARCH_SCAN as usize) {
+            last_position = Some(position);
+            let room_id = position.1;
+            if !app_rooms.contains(&room_id) {
+                continue;
+            }
+            let room = self.rooms.get(&room_id).expect("Room not found");
+            if filter.matches(room) {
+                rooms.push(Room::clone(room));
+                if rooms.len() == limit {
+                    break;
+                }
+            }
+        }
+
+        let next_cursor = match positions.next() {
+            None => None,
+            Some(_) => {
+                let cursor = RoomCursor {
+                    key: sort.key,
+                    descending: sort.descending,
+                    position: last_position.unwrap(),
+                };
+                Some(Base64VecU8(cursor.try_to_vec().unwrap()))
+            }
+        };
+        RoomPage { rooms, next_cursor }
+    }
+
     pub fn get_number_of_available_rooms(&self, app_name: AppName) -> usize {
         let wrapped_app_rooms = self
             .available_rooms_per_app
@@ -61,18 +268,15 @@ impl Contract {
             .expect("App rooms not found");
 
         let room_ids: Vec<&RoomId> = app_rooms.iter().collect();
-        let number_of_rooms = room_ids.len() as usize;
+        let number_of_rooms = room_ids.len();
         if number_of_rooms == 0 {
             panic!("There are currently no available rooms")
         }
 
         let rnd_idx = self.get_random_in_range(0, number_of_rooms, 0);
-        let rnd_room_id = room_ids.get(rnd_idx).expect("Random room id not found");
+        let rnd_room_id = *room_ids.get(rnd_idx).expect("Random room id not found");
 
-        let random_room = self.rooms
-            .get(rnd_room_id)
-            .expect("Random room not found")
-            .clone();
+        let random_room = Room::clone(self.rooms.get(rnd_room_id).expect("Random room not found"));
 
         random_room
     }
@@ -83,3 +287,276 @@ impl Contract {
         random_in_range.floor() as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    fn create_room(
+        contract: &mut Contract,
+        owner_idx: usize,
+        tags: &[&str],
+        limit: usize,
+    ) -> RoomId {
+        let mut config = room_config(APP_NAME, limit);
+        config.tags = Some(tags.iter().map(|tag| tag.to_string()).collect());
+        set_context_at_timestamp(&accounts(owner_idx), DEPOSIT, 10 + owner_idx as u64);
+        contract.create_room(config)
+    }
+
+    /// Creates the rooms 0 to 3 owned by alice, bob, charlie and danny.
+    fn setup_rooms(contract: &mut Contract) {
+        register_app(contract, &accounts(5), APP_NAME);
+        create_room(contract, 0, &["mode:ranked", "region:eu"], 2);
+        create_room(contract, 1, &["mode:casual", "region:eu"], 4);
+        create_room(contract, 2, &["mode:ranked"], 3);
+        create_room(contract, 3, &[], 4);
+    }
+
+    fn search(
+        contract: &Contract,
+        filter: Option<RoomFilter>,
+        sort: Option<RoomSort>,
+        cursor: Option<Base64VecU8>,
+        limit: Option<u64>,
+    ) -> (Vec<RoomId>, Option<Base64VecU8>) {
+        let page = contract.search_rooms(APP_NAME.to_string(), filter, sort, cursor, limit);
+        let room_ids = page.rooms.iter().map(|room| room.room_id).collect();
+        (room_ids, page.next_cursor)
+    }
+
+    #[test]
+    fn test_get_app_rooms_default_limit() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        for idx in 0..DEFAULT_SEARCH_LIMIT {
+            set_context(&format!("player{}.near", idx).parse().unwrap(), DEPOSIT);
+            contract.create_room(room_config(APP_NAME, 2));
+        }
+
+        assert_eq!(
+            contract
+                .get_app_rooms(APP_NAME.to_string(), None, None)
+                .len(),
+            DEFAULT_SEARCH_LIMIT as usize
+        );
+        assert!(contract
+            .get_app_rooms(APP_NAME.to_string(), Some(U128(u128::MAX)), None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_search_rooms_filter() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        set_context(&accounts(4), DEPOSIT);
+        contract.join(0, APP_NAME.to_string(), None);
+
+        let filter = RoomFilter {
+            tags: Some(vec!["region:eu".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            search(&contract, Some(filter), None, None, None).0,
+            vec![0, 1]
+        );
+
+        let filter = RoomFilter {
+            tags: Some(vec!["mode:ranked".to_string()]),
+            min_free_seats: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(search(&contract, Some(filter), None, None, None).0, vec![2]);
+
+        let filter = RoomFilter {
+            owner_id: Some(accounts(3)),
+            ..Default::default()
+        };
+        assert_eq!(search(&contract, Some(filter), None, None, None).0, vec![3]);
+
+        let filter = RoomFilter {
+            min_player_limit: Some(3),
+            max_player_limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(search(&contract, Some(filter), None, None, None).0, vec![2]);
+    }
+
+    #[test]
+    fn test_search_rooms_sort() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+
+        let sort = RoomSort {
+            key: RoomSortKey::CreatedAt,
+            descending: false,
+        };
+        assert_eq!(
+            search(&contract, None, Some(sort), None, None).0,
+            vec![0, 1, 2, 3]
+        );
+
+        let sort = RoomSort {
+            key: RoomSortKey::RoomId,
+            descending: true,
+        };
+        assert_eq!(
+            search(&contract, None, Some(sort), None, None).0,
+            vec![3, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_search_rooms_cursor() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+
+        let (room_ids, cursor) = search(&contract, None, None, None, Some(2));
+        assert_eq!(room_ids, vec![0, 1]);
+
+        // The removed room of the first page doesn't shift the next one.
+        set_context(&accounts(0), 0);
+        contract.remove(0, APP_NAME.to_string());
+        let (room_ids, cursor) = search(&contract, None, None, cursor, Some(2));
+        assert_eq!(room_ids, vec![2, 3]);
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn test_search_rooms_cursor_descending() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        let sort = || {
+            Some(RoomSort {
+                key: RoomSortKey::RoomId,
+                descending: true,
+            })
+        };
+
+        let (room_ids, cursor) = search(&contract, None, sort(), None, Some(3));
+        assert_eq!(room_ids, vec![3, 2, 1]);
+        let (room_ids, cursor) = search(&contract, None, sort(), cursor, Some(3));
+        assert_eq!(room_ids, vec![0]);
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn test_search_rooms_resumes_scan() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        register_app(&mut contract, &accounts(5), "other");
+        set_context(&accounts(4), DEPOSIT);
+        contract.create_room(room_config("other", 2));
+        contract.next_room_id += MAX_SEARCH_SCAN;
+        let room_id = create_room(&mut contract, 4, &[], 2);
+
+        // The rooms of the other app and the missing room ids are skipped, but counted in the scan.
+        let filter = || {
+            Some(RoomFilter {
+                owner_id: Some(accounts(4)),
+                ..Default::default()
+            })
+        };
+        let (room_ids, cursor) = search(&contract, filter(), None, None, None);
+        assert!(room_ids.is_empty());
+        let (room_ids, cursor) = search(&contract, filter(), None, cursor, None);
+        assert_eq!(room_ids, vec![room_id]);
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn test_search_rooms_sort_fill_ratio() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        let sort = |descending| {
+            Some(RoomSort {
+                key: RoomSortKey::FillRatio,
+                descending,
+            })
+        };
+        assert_eq!(
+            search(&contract, None, sort(false), None, None).0,
+            vec![1, 3, 2, 0]
+        );
+
+        // The fill index follows the joined players and the invites.
+        set_context(&accounts(4), DEPOSIT);
+        contract.join(1, APP_NAME.to_string(), None);
+        set_context(&accounts(2), DEPOSIT);
+        contract.invite(2, accounts(5));
+        assert_eq!(
+            search(&contract, None, sort(false), None, None).0,
+            vec![3, 0, 1, 2]
+        );
+        assert_eq!(
+            search(&contract, None, sort(true), None, None).0,
+            vec![2, 1, 0, 3]
+        );
+
+        set_context(&accounts(4), 0);
+        contract.leave(1, APP_NAME.to_string());
+        set_context(&accounts(2), 0);
+        contract.revoke_invite(2, accounts(5));
+        set_context(&accounts(0), 0);
+        contract.close(0, APP_NAME.to_string());
+        assert_eq!(
+            search(&contract, None, sort(false), None, None).0,
+            vec![1, 3, 2]
+        );
+
+        contract.open(0, APP_NAME.to_string());
+        assert_eq!(
+            search(&contract, None, sort(false), None, None).0,
+            vec![1, 3, 2, 0]
+        );
+    }
+
+    #[test]
+    fn test_search_rooms_cursor_fill_ratio() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+        let sort = || {
+            Some(RoomSort {
+                key: RoomSortKey::FillRatio,
+                descending: false,
+            })
+        };
+
+        let (room_ids, cursor) = search(&contract, None, sort(), None, Some(2));
+        assert_eq!(room_ids, vec![1, 3]);
+
+        // The room of the first page moves after the cursor once it fills up.
+        set_context(&accounts(4), DEPOSIT);
+        contract.join(1, APP_NAME.to_string(), None);
+        let (room_ids, cursor) = search(&contract, None, sort(), cursor, Some(2));
+        assert_eq!(room_ids, vec![2, 0]);
+        let (room_ids, cursor) = search(&contract, None, sort(), cursor, Some(2));
+        assert_eq!(room_ids, vec![1]);
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The cursor doesn't match the sort")]
+    fn test_search_rooms_cursor_of_other_sort() {
+        let mut contract = new_contract();
+        setup_rooms(&mut contract);
+
+        let (_, cursor) = search(&contract, None, None, None, Some(2));
+        let sort = RoomSort {
+            key: RoomSortKey::CreatedAt,
+            descending: false,
+        };
+        search(&contract, None, Some(sort), cursor, Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate room tag")]
+    fn test_create_room_duplicate_tag() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+
+        create_room(&mut contract, 0, &["mode:ranked", "mode:ranked"], 2);
+    }
+}
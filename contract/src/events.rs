@@ -0,0 +1,446 @@
+use crate::*;
+use near_sdk::log;
+use near_sdk::serde_json;
+
+pub const EVENT_STANDARD: &str = "room";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// The room event data. The accounts that are not involved in the event are omitted.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomEventData<'a> {
+    pub room_id: RoomId,
+    pub app_name: &'a AppName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<&'a AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<&'a AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub players: Option<&'a [AccountId]>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RatingEventData<'a> {
+    pub room_id: RoomId,
+    pub app_name: &'a AppName,
+    pub account_id: &'a AccountId,
+    pub old_rating: u32,
+    pub new_rating: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AppBanEventData<'a> {
+    pub app_name: &'a AppName,
+    pub account_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+    /// The app owner who has changed the ban.
+    pub moderator_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomStateEventData<'a> {
+    pub room_id: RoomId,
+    pub app_name: &'a AppName,
+    /// The writer of the value.
+    pub account_id: &'a AccountId,
+    pub key: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TurnEventData<'a> {
+    pub room_id: RoomId,
+    pub app_name: &'a AppName,
+    /// The player of the turn.
+    pub account_id: &'a AccountId,
+    pub turn: u64,
+}
+
+/// The [NEP-297](https://nomicon.io/Standards/EventsFormat) events of the room standard.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomEvent<'a> {
+    RoomCreated(&'a [RoomEventData<'a>]),
+    PlayerJoined(&'a [RoomEventData<'a>]),
+    PlayerLeft(&'a [RoomEventData<'a>]),
+    RoomOpened(&'a [RoomEventData<'a>]),
+    RoomClosed(&'a [RoomEventData<'a>]),
+    RoomRemoved(&'a [RoomEventData<'a>]),
+    PlayerKicked(&'a [RoomEventData<'a>]),
+    PlayerBanned(&'a [RoomEventData<'a>]),
+    RatingChanged(&'a [RatingEventData<'a>]),
+    ResultFinalized(&'a [RoomEventData<'a>]),
+    ResultDisputed(&'a [RoomEventData<'a>]),
+    GameStarted(&'a [RoomEventData<'a>]),
+    GameFinished(&'a [RoomEventData<'a>]),
+    RoomArchived(&'a [RoomEventData<'a>]),
+    OwnershipTransferred(&'a [RoomEventData<'a>]),
+    ModeratorAdded(&'a [RoomEventData<'a>]),
+    ModeratorRemoved(&'a [RoomEventData<'a>]),
+    PlayerMuted(&'a [RoomEventData<'a>]),
+    PlayerUnmuted(&'a [RoomEventData<'a>]),
+    PlayerUnbanned(&'a [RoomEventData<'a>]),
+    AppBanned(&'a [AppBanEventData<'a>]),
+    AppUnbanned(&'a [AppBanEventData<'a>]),
+    RoomStateSet(&'a [RoomStateEventData<'a>]),
+    RoomStateRemoved(&'a [RoomStateEventData<'a>]),
+    MoveSubmitted(&'a [TurnEventData<'a>]),
+    TurnSkipped(&'a [TurnEventData<'a>]),
+    PlayerForfeited(&'a [TurnEventData<'a>]),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a RoomEvent<'a>,
+}
+
+impl RoomEvent<'_> {
+    pub fn emit(&self) {
+        let event_log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        log!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&event_log).expect("Failed to serialize the event")
+        );
+    }
+}
+
+fn room_data(room: &Room) -> RoomEventData<'_> {
+    RoomEventData {
+        room_id: room.room_id,
+        app_name: &room.app_name,
+        owner_id: Some(&room.owner_id),
+        account_id: None,
+        players: None,
+    }
+}
+
+fn player_data<'a>(room: &'a Room, account_id: &'a AccountId) -> RoomEventData<'a> {
+    RoomEventData {
+        account_id: Some(account_id),
+        owner_id: None,
+        ..room_data(room)
+    }
+}
+
+pub fn emit_room_created(room: &Room) {
+    RoomEvent::RoomCreated(&[room_data(room)]).emit();
+}
+
+pub fn emit_player_joined(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerJoined(&[player_data(room, account_id)]).emit();
+}
+
+pub fn emit_player_left(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerLeft(&[player_data(room, account_id)]).emit();
+}
+
+pub fn emit_room_opened(room: &Room) {
+    RoomEvent::RoomOpened(&[room_data(room)]).emit();
+}
+
+pub fn emit_room_closed(room: &Room) {
+    RoomEvent::RoomClosed(&[room_data(room)]).emit();
+}
+
+pub fn emit_room_removed(room: &Room) {
+    RoomEvent::RoomRemoved(&[RoomEventData {
+        players: Some(&room.players),
+        ..room_data(room)
+    }])
+    .emit();
+}
+
+pub fn emit_player_kicked(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerKicked(&[RoomEventData {
+        account_id: Some(account_id),
+        ..room_data(room)
+    }])
+    .emit();
+}
+
+pub fn emit_player_banned(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerBanned(&[RoomEventData {
+        account_id: Some(account_id),
+        ..room_data(room)
+    }])
+    .emit();
+}
+
+pub fn emit_rating_changed(room: &Room, account_id: &AccountId, old_rating: u32, new_rating: u32) {
+    RoomEvent::RatingChanged(&[RatingEventData {
+        room_id: room.room_id,
+        app_name: &room.app_name,
+        account_id,
+        old_rating,
+        new_rating,
+    }])
+    .emit();
+}
+
+pub fn emit_result_finalized(room: &Room) {
+    RoomEvent::ResultFinalized(&[room_data(room)]).emit();
+}
+
+pub fn emit_result_disputed(room: &Room) {
+    RoomEvent::ResultDisputed(&[room_data(room)]).emit();
+}
+
+pub fn emit_game_started(room: &Room) {
+    RoomEvent::GameStarted(&[room_data(room)]).emit();
+}
+
+pub fn emit_game_finished(room: &Room) {
+    RoomEvent::GameFinished(&[room_data(room)]).emit();
+}
+
+pub fn emit_room_archived(room: &Room) {
+    RoomEvent::RoomArchived(&[room_data(room)]).emit();
+}
+
+/// The `account_id` is the previous owner.
+pub fn emit_ownership_transferred(room: &Room, previous_owner_id: &AccountId) {
+    RoomEvent::OwnershipTransferred(&[RoomEventData {
+        account_id: Some(previous_owner_id),
+        ..room_data(room)
+    }])
+    .emit();
+}
+
+pub fn emit_player_unbanned(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerUnbanned(&[RoomEventData {
+        account_id: Some(account_id),
+        ..room_data(room)
+    }])
+    .emit();
+}
+
+pub fn emit_app_banned(app_name: &AppName, app_ban: &AppBan) {
+    RoomEvent::AppBanned(&[AppBanEventData {
+        app_name,
+        account_id: &app_ban.account_id,
+        reason: app_ban.reason.as_deref(),
+        moderator_id: &predecessor_account_id(),
+    }])
+    .emit();
+}
+
+pub fn emit_app_unbanned(app_name: &AppName, app_ban: &AppBan) {
+    RoomEvent::AppUnbanned(&[AppBanEventData {
+        app_name,
+        account_id: &app_ban.account_id,
+        reason: None,
+        moderator_id: &predecessor_account_id(),
+    }])
+    .emit();
+}
+
+pub fn emit_moderator_added(room: &Room, account_id: &AccountId) {
+    RoomEvent::ModeratorAdded(&[player_data(room, account_id)]).emit();
+}
+
+pub fn emit_moderator_removed(room: &Room, account_id: &AccountId) {
+    RoomEvent::ModeratorRemoved(&[player_data(room, account_id)]).emit();
+}
+
+pub fn emit_player_muted(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerMuted(&[player_data(room, account_id)]).emit();
+}
+
+pub fn emit_player_unmuted(room: &Room, account_id: &AccountId) {
+    RoomEvent::PlayerUnmuted(&[player_data(room, account_id)]).emit();
+}
+
+fn room_state_data<'a>(
+    room: &'a Room,
+    account_id: &'a AccountId,
+    key: &'a str,
+) -> RoomStateEventData<'a> {
+    RoomStateEventData {
+        room_id: room.room_id,
+        app_name: &room.app_name,
+        account_id,
+        key,
+    }
+}
+
+pub fn emit_room_state_set(room: &Room, account_id: &AccountId, key: &str) {
+    RoomEvent::RoomStateSet(&[room_state_data(room, account_id, key)]).emit();
+}
+
+pub fn emit_room_state_removed(room: &Room, account_id: &AccountId, key: &str) {
+    RoomEvent::RoomStateRemoved(&[room_state_data(room, account_id, key)]).emit();
+}
+
+fn turn_data<'a>(room: &'a Room, account_id: &'a AccountId, turn: u64) -> TurnEventData<'a> {
+    TurnEventData {
+        room_id: room.room_id,
+        app_name: &room.app_name,
+        account_id,
+        turn,
+    }
+}
+
+pub fn emit_move_submitted(room: &Room, account_id: &AccountId, turn: u64) {
+    RoomEvent::MoveSubmitted(&[turn_data(room, account_id, turn)]).emit();
+}
+
+pub fn emit_turn_skipped(room: &Room, account_id: &AccountId, turn: u64) {
+    RoomEvent::TurnSkipped(&[turn_data(room, account_id, turn)]).emit();
+}
+
+pub fn emit_player_forfeited(room: &Room, account_id: &AccountId, turn: u64) {
+    RoomEvent::PlayerForfeited(&[turn_data(room, account_id, turn)]).emit();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    fn event_log(event: &str, data: &str) -> String {
+        format!(
+            r#"EVENT_JSON:{{"standard":"room","version":"1.0.0","event":"{}","data":[{}]}}"#,
+            event, data
+        )
+    }
+
+    #[test]
+    fn test_room_created_event() {
+        let mut contract = new_contract();
+        setup_room(&mut contract, 3, 1);
+
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "room_created",
+                r#"{"room_id":0,"app_name":"app","owner_id":"alice"}"#
+            )]
+        );
+    }
+
+    #[test]
+    fn test_player_joined_and_left_events() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "player_joined",
+                r#"{"room_id":0,"app_name":"app","account_id":"bob"}"#
+            )]
+        );
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "player_left",
+                r#"{"room_id":0,"app_name":"app","account_id":"bob"}"#
+            )]
+        );
+    }
+
+    #[test]
+    fn test_room_closed_and_opened_events() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(0), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "room_closed",
+                r#"{"room_id":0,"app_name":"app","owner_id":"alice"}"#
+            )]
+        );
+
+        set_context(&accounts(0), 0);
+        contract.open(room_id, APP_NAME.to_string());
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "room_opened",
+                r#"{"room_id":0,"app_name":"app","owner_id":"alice"}"#
+            )]
+        );
+    }
+
+    #[test]
+    fn test_room_removed_event() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "room_removed",
+                r#"{"room_id":0,"app_name":"app","owner_id":"alice","players":["alice","bob"]}"#
+            )]
+        );
+    }
+
+    #[test]
+    fn test_player_kicked_and_banned_events() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(0), 0);
+        contract.ban(room_id, accounts(1), None);
+        assert_eq!(
+            get_logs(),
+            vec![
+                event_log(
+                    "player_kicked",
+                    r#"{"room_id":0,"app_name":"app","owner_id":"alice","account_id":"bob"}"#
+                ),
+                event_log(
+                    "player_banned",
+                    r#"{"room_id":0,"app_name":"app","owner_id":"alice","account_id":"bob"}"#
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ban_without_kick_event() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(0), 0);
+        contract.ban(room_id, accounts(1), None);
+        assert_eq!(
+            get_logs(),
+            vec![event_log(
+                "player_banned",
+                r#"{"room_id":0,"app_name":"app","owner_id":"alice","account_id":"bob"}"#
+            )]
+        );
+    }
+}
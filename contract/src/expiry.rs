@@ -0,0 +1,246 @@
+use crate::*;
+use near_sdk::json_types::U64;
+use near_sdk::{env, Timestamp};
+
+/// The rooms expire after a week without activity by default.
+pub const DEFAULT_ROOM_TTL: Timestamp = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+impl Room {
+    /// Records the activity in the room, which postpones its expiry.
+    pub fn touch(&mut self) {
+        self.last_activity = U64(env::block_timestamp());
+    }
+
+    pub fn is_expired(&self, room_ttl: Timestamp, now: Timestamp) -> bool {
+        self.last_activity.0.saturating_add(room_ttl) <= now
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CleanupResult {
+    /// The number of the removed rooms.
+    pub removed: usize,
+    /// Passed to the next call to continue the scan. Not set once the last room is scanned.
+    pub next_room_id: Option<RoomId>,
+}
+
+impl Contract {
+    /// Removes the expired room and takes the cleanup bounty out of the storage balance of the
+    /// owner, which was freed by the room. Returns the bounty.
+    fn internal_remove_expired_room(
+        &mut self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        bounty_percent: u32,
+    ) -> Balance {
+        let owner_id = self.rooms.get(room_id).unwrap().owner_id.clone();
+        let used_bytes = self.internal_get_account(&owner_id).used_bytes;
+
+        self.internal_remove_room(room_id, app_name);
+
+        let mut owner = self.internal_get_account(&owner_id);
+        let freed_bytes = used_bytes.saturating_sub(owner.used_bytes);
+        let bounty =
+            Balance::from(freed_bytes) * env::storage_byte_cost() * Balance::from(bounty_percent)
+                / 100;
+        owner.storage_balance -= bounty;
+        self.internal_set_account(&owner_id, owner);
+
+        bounty
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Scans `limit` room ids starting from `from_room_id` and removes the rooms of the app
+    /// without activity for longer than the room TTL of the app. Anyone can call it and gets the
+    /// cleanup bounty of the app, if any.
+    pub fn cleanup_expired(
+        &mut self,
+        app_name: AppName,
+        from_room_id: Option<RoomId>,
+        limit: u64,
+    ) -> CleanupResult {
+        self.assert_app_not_paused(&app_name);
+        let app = self.internal_get_app(&app_name);
+        let room_ttl = app.room_ttl.0;
+        let bounty_percent = app.cleanup_bounty_percent;
+        let now = env::block_timestamp();
+
+        let start = from_room_id.unwrap_or(0);
+        let end = start.saturating_add(limit).min(self.next_room_id);
+        let expired_room_ids: Vec<RoomId> = (start..end)
+            .filter(|room_id| {
+                self.rooms.get(room_id).is_some_and(|room| {
+                    room.app_name.eq(&app_name) && room.is_expired(room_ttl, now)
+                })
+            })
+            .collect();
+
+        let mut bounty = 0;
+        for room_id in &expired_room_ids {
+            bounty += self.internal_remove_expired_room(room_id, &app_name, bounty_percent);
+        }
+        if bounty > 0 {
+            Promise::new(predecessor_account_id()).transfer(bounty);
+        }
+
+        CleanupResult {
+            removed: expired_room_ids.len(),
+            next_room_id: (end < self.next_room_id).then_some(end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppConfig;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::ONE_NEAR;
+
+    const ROOM_TTL: Timestamp = 1_000;
+
+    fn register_app_with_ttl(contract: &mut Contract, bounty_percent: Option<u32>) {
+        let config = AppConfig {
+            room_ttl: Some(U64(ROOM_TTL)),
+            cleanup_bounty_percent: bounty_percent,
+            ..app_config()
+        };
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), config);
+    }
+
+    fn create_room(contract: &mut Contract, owner_id: &AccountId, timestamp: Timestamp) -> RoomId {
+        set_context_at_timestamp(owner_id, DEPOSIT, timestamp);
+        contract.create_room(room_config(APP_NAME, 3))
+    }
+
+    #[test]
+    fn test_room_records_activity() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+        let room_id = create_room(&mut contract, &accounts(0), 100);
+
+        set_context_at_timestamp(&accounts(1), DEPOSIT, 500);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.created_at, U64(100));
+        assert_eq!(room.last_activity, U64(500));
+    }
+
+    #[test]
+    fn test_cleanup_expired() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+        let stale_room_id = create_room(&mut contract, &accounts(0), 0);
+        let active_room_id = create_room(&mut contract, &accounts(1), 0);
+
+        set_context_at_timestamp(&accounts(2), DEPOSIT, 500);
+        contract.join(stale_room_id, APP_NAME.to_string(), None);
+        set_context_at_timestamp(&accounts(3), DEPOSIT, ROOM_TTL);
+        contract.join(active_room_id, APP_NAME.to_string(), None);
+
+        set_context_at_timestamp(&accounts(4), 0, ROOM_TTL + 500);
+        let result = contract.cleanup_expired(APP_NAME.to_string(), None, 10);
+        assert_eq!(result.removed, 1);
+        assert_eq!(result.next_room_id, None);
+        assert!(contract.rooms.get(&stale_room_id).is_none());
+        assert!(contract.rooms.get(&active_room_id).is_some());
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            1
+        );
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(2))
+            .is_none());
+        assert_eq!(contract.internal_get_account(&accounts(2)).used_bytes, 0);
+        assert_eq!(transferred_to(&accounts(4)), 0);
+    }
+
+    #[test]
+    fn test_cleanup_expired_limit() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+        for idx in 0..3 {
+            create_room(&mut contract, &accounts(idx), 0);
+        }
+
+        set_context_at_timestamp(&accounts(4), 0, ROOM_TTL);
+        let result = contract.cleanup_expired(APP_NAME.to_string(), None, 2);
+        assert_eq!(result.removed, 2);
+        assert_eq!(result.next_room_id, Some(2));
+        let result = contract.cleanup_expired(APP_NAME.to_string(), result.next_room_id, 2);
+        assert_eq!(result.removed, 1);
+        assert_eq!(result.next_room_id, None);
+        let result = contract.cleanup_expired(APP_NAME.to_string(), None, 2);
+        assert_eq!(result.removed, 0);
+    }
+
+    #[test]
+    fn test_cleanup_expired_skips_other_apps() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+        register_app(&mut contract, &accounts(5), "other_app");
+        set_context(&accounts(1), DEPOSIT);
+        let other_room_id = contract.create_room(room_config("other_app", 3));
+        let room_id = create_room(&mut contract, &accounts(0), 0);
+
+        set_context_at_timestamp(&accounts(4), 0, DEFAULT_ROOM_TTL);
+        let result = contract.cleanup_expired(APP_NAME.to_string(), None, 10);
+        assert_eq!(result.removed, 1);
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert!(contract.rooms.get(&other_room_id).is_some());
+    }
+
+    #[test]
+    fn test_is_expired_with_max_ttl() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+        let room_id = create_room(&mut contract, &accounts(0), 100);
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert!(!room.is_expired(Timestamp::MAX, Timestamp::MAX - 1));
+        assert!(room.is_expired(Timestamp::MAX, Timestamp::MAX));
+    }
+
+    #[test]
+    fn test_cleanup_expired_pays_bounty() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, Some(10));
+        let room_id = create_room(&mut contract, &accounts(0), 0);
+        let owner = contract.internal_get_account(&accounts(0));
+
+        set_context_at_timestamp(&accounts(4), 0, ROOM_TTL);
+        let result = contract.cleanup_expired(APP_NAME.to_string(), None, 1);
+        assert_eq!(result.removed, 1);
+        assert!(contract.rooms.get(&room_id).is_none());
+
+        let bounty = transferred_to(&accounts(4));
+        assert!(bounty > 0);
+        let freed_balance = Balance::from(owner.used_bytes) * env::storage_byte_cost();
+        assert!(bounty <= freed_balance / 10);
+        assert_eq!(
+            contract.internal_get_account(&accounts(0)).storage_balance,
+            owner.storage_balance - bounty
+        );
+    }
+
+    #[test]
+    fn test_cleanup_expired_refunds_entry_fees() {
+        let mut contract = new_contract();
+        register_app_with_ttl(&mut contract, None);
+
+        let mut config = room_config(APP_NAME, 3);
+        config.entry_fee = Some(U128(ONE_NEAR));
+        set_context_at_timestamp(&accounts(0), DEPOSIT + ONE_NEAR, 0);
+        contract.create_room(config);
+
+        set_context_at_timestamp(&accounts(4), 0, ROOM_TTL);
+        contract.cleanup_expired(APP_NAME.to_string(), None, 1);
+        assert_eq!(transferred_to(&accounts(0)), ONE_NEAR);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::*;
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::serde_json;
+use near_sdk::{env, log, require, Gas, PromiseOrValue, PromiseResult};
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_ON_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+/// The max player limit of the room with the token entry fee. Every player can get a transfer
+/// with its callback when the prize pool is paid out or refunded, and all of them must fit into
+/// the gas of one call.
+pub const MAX_FT_ROOM_PLAYERS: usize = 8;
+
+/// The `msg` of `ft_transfer_call` that pays the entry fee of the room with the tokens.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FtTransferMessage {
+    Join {
+        room_id: RoomId,
+        app_name: AppName,
+        access_code: Option<String>,
+    },
+    CreateRoom {
+        room_config: Box<RoomConfig>,
+    },
+}
+
+/// Sends the tokens and keeps them claimable by the receiver if the transfer fails.
+pub fn ft_transfer_with_callback(token_id: &AccountId, receiver_id: &AccountId, amount: Balance) {
+    ext_ft_core::ext(token_id.clone())
+        .with_attached_deposit(1)
+        .with_static_gas(GAS_FOR_FT_TRANSFER)
+        .ft_transfer(receiver_id.clone(), U128(amount), None)
+        .then(
+            Contract::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_ON_FT_TRANSFER)
+                .on_ft_transfer(token_id.clone(), receiver_id.clone(), U128(amount)),
+        );
+}
+
+impl Contract {
+    fn assert_fee_token(fee_token_id: &Option<AccountId>, token_id: &AccountId) {
+        if fee_token_id.as_ref() != Some(token_id) {
+            panic!("The token is not the entry fee token of the room")
+        }
+    }
+
+    fn internal_join_with_ft(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+        room_id: RoomId,
+        app_name: AppName,
+        access_code: Option<String>,
+    ) -> Balance {
+        let room = self.internal_get_app_room_mut(&room_id, &app_name);
+        Self::assert_fee_token(&room.fee_token_id, token_id);
+        if room.nft_gate.is_some() {
+            panic!("The NFT-gated room can't be joined with the tokens")
+        }
+        let entry_fee = room.entry_fee.0;
+        require!(amount >= entry_fee, "The amount is less than the entry fee");
+        room.prize_pool.0 += entry_fee;
+        let fill_ratio = room.fill_ratio();
+
+        let mut account = self.internal_get_account(sender_id);
+        account.start_storage_tracker();
+
+        self.internal_join(&room_id, &app_name, sender_id, access_code);
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(sender_id, account);
+        self.internal_update_room_fill(&room_id, fill_ratio);
+
+        entry_fee
+    }
+
+    fn internal_create_room_with_ft(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+        room_config: RoomConfig,
+    ) -> Balance {
+        Self::assert_fee_token(&room_config.fee_token_id, token_id);
+        self.assert_hidden_room_allowed(&room_config);
+        let entry_fee = room_config.entry_fee.map_or(0, |entry_fee| entry_fee.0);
+        require!(amount >= entry_fee, "The amount is less than the entry fee");
+
+        let account = self.internal_get_account(sender_id);
+        self.internal_create_room(sender_id, account, room_config);
+
+        entry_fee
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Pays the entry fee with the tokens. The player must have the storage deposit already.
+    /// The tokens above the entry fee are returned to the sender.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_not_paused();
+        let token_id = predecessor_account_id();
+        let message: FtTransferMessage = serde_json::from_str(&msg).expect("Invalid message");
+
+        let entry_fee = match message {
+            FtTransferMessage::Join {
+                room_id,
+                app_name,
+                access_code,
+            } => {
+                self.assert_app_not_paused(&app_name);
+                self.internal_join_with_ft(
+                    &token_id,
+                    &sender_id,
+                    amount.0,
+                    room_id,
+                    app_name,
+                    access_code,
+                )
+            }
+            FtTransferMessage::CreateRoom { room_config } => {
+                self.assert_app_not_paused(&room_config.app_name);
+                self.internal_create_room_with_ft(&token_id, &sender_id, amount.0, *room_config)
+            }
+        };
+
+        PromiseOrValue::Value(U128(amount.0 - entry_fee))
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Keeps the tokens of the failed transfer claimable by the receiver. The contract pays for
+    /// the storage of the claim, so the tokens are kept even if the receiver has no storage
+    /// balance or is not registered.
+    #[private]
+    pub fn on_ft_transfer(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128) {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        log!(
+            "Failed to transfer {} of {} to {}, the tokens can be claimed",
+            amount.0,
+            token_id,
+            receiver_id
+        );
+
+        let key = (receiver_id, token_id);
+        let unclaimed = self.ft_claims.get(&key).copied().unwrap_or(0);
+        self.ft_claims.insert(key, unclaimed + amount.0);
+        self.ft_claims.flush();
+    }
+
+    /// Retries the failed transfers of the token to the caller.
+    pub fn claim_ft(&mut self, token_id: AccountId) {
+        self.assert_not_paused();
+        let account_id = predecessor_account_id();
+
+        let amount = self
+            .ft_claims
+            .remove(&(account_id.clone(), token_id.clone()))
+            .expect("Nothing to claim");
+        self.ft_claims.flush();
+
+        ft_transfer_with_callback(&token_id, &account_id, amount);
+    }
+
+    pub fn get_ft_claim(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(
+            self.ft_claims
+                .get(&(account_id, token_id))
+                .copied()
+                .unwrap_or(0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+
+    const ENTRY_FEE: Balance = 100;
+
+    fn token_id() -> AccountId {
+        "token.near".parse().unwrap()
+    }
+
+    fn ft_room_config() -> RoomConfig {
+        let mut config = room_config(APP_NAME, 3);
+        config.fee_token_id = Some(token_id());
+        config.entry_fee = Some(U128(ENTRY_FEE));
+        config
+    }
+
+    fn register_ft_app(contract: &mut Contract) {
+        let mut config = app_config();
+        config.accepted_tokens = Some(vec![token_id()]);
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(APP_NAME.to_string(), config);
+    }
+
+    fn register_account(contract: &mut Contract, account_id: &AccountId) {
+        set_context(account_id, DEPOSIT);
+        contract.storage_deposit(None, None);
+    }
+
+    fn ft_transfer_call(
+        contract: &mut Contract,
+        sender_id: &AccountId,
+        amount: Balance,
+        message: FtTransferMessage,
+    ) -> Balance {
+        set_context(&token_id(), 0);
+        match contract.ft_on_transfer(
+            sender_id.clone(),
+            U128(amount),
+            serde_json::to_string(&message).unwrap(),
+        ) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Unexpected promise"),
+        }
+    }
+
+    fn setup_ft_room(contract: &mut Contract) -> RoomId {
+        register_ft_app(contract);
+        for idx in 0..3 {
+            register_account(contract, &accounts(idx));
+        }
+
+        let message = FtTransferMessage::CreateRoom {
+            room_config: Box::new(ft_room_config()),
+        };
+        assert_eq!(
+            ft_transfer_call(contract, &accounts(0), ENTRY_FEE, message),
+            0
+        );
+        let room_id = 0;
+        for idx in 1..3 {
+            let message = FtTransferMessage::Join {
+                room_id,
+                app_name: APP_NAME.to_string(),
+                access_code: None,
+            };
+            ft_transfer_call(contract, &accounts(idx), ENTRY_FEE, message);
+        }
+        room_id
+    }
+
+    /// Returns the amounts of `ft_transfer` calls to the token contract by the receivers.
+    fn ft_transfers() -> Vec<(AccountId, Balance)> {
+        get_created_receipts()
+            .iter()
+            .filter(|receipt| receipt.receiver_id.eq(&token_id()))
+            .flat_map(|receipt| receipt.actions.iter())
+            .filter_map(|action| match action {
+                VmAction::FunctionCall {
+                    function_name,
+                    args,
+                    ..
+                } if function_name == "ft_transfer" => {
+                    let args: serde_json::Value = serde_json::from_slice(args).unwrap();
+                    Some((
+                        args["receiver_id"].as_str().unwrap().parse().unwrap(),
+                        args["amount"].as_str().unwrap().parse().unwrap(),
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ft_entry_fee_is_escrowed() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.players, vec![accounts(0), accounts(1), accounts(2)]);
+        assert_eq!(room.prize_pool.0, 3 * ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_ft_excess_is_returned() {
+        let mut contract = new_contract();
+        register_ft_app(&mut contract);
+        register_account(&mut contract, &accounts(0));
+
+        let message = FtTransferMessage::CreateRoom {
+            room_config: Box::new(ft_room_config()),
+        };
+        assert_eq!(
+            ft_transfer_call(&mut contract, &accounts(0), ENTRY_FEE + 5, message),
+            5
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The token is not the entry fee token of the room")]
+    fn test_join_with_wrong_token() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+        register_account(&mut contract, &accounts(3));
+
+        set_context(&accounts(4), 0);
+        contract.ft_on_transfer(
+            accounts(3),
+            U128(ENTRY_FEE),
+            serde_json::to_string(&FtTransferMessage::Join {
+                room_id,
+                app_name: APP_NAME.to_string(),
+                access_code: None,
+            })
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount is less than the entry fee")]
+    fn test_join_with_insufficient_amount() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+        register_account(&mut contract, &accounts(3));
+
+        let message = FtTransferMessage::Join {
+            room_id,
+            app_name: APP_NAME.to_string(),
+            access_code: None,
+        };
+        ft_transfer_call(&mut contract, &accounts(3), ENTRY_FEE - 1, message);
+    }
+
+    #[test]
+    #[should_panic(expected = "The token is not accepted by the app")]
+    fn test_create_room_with_not_accepted_token() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        register_account(&mut contract, &accounts(0));
+
+        let message = FtTransferMessage::CreateRoom {
+            room_config: Box::new(ft_room_config()),
+        };
+        ft_transfer_call(&mut contract, &accounts(0), ENTRY_FEE, message);
+    }
+
+    #[test]
+    #[should_panic(expected = "The player limit of the room with the token entry fee exceeds 8")]
+    fn test_create_ft_room_over_player_limit() {
+        let mut contract = new_contract();
+        register_ft_app(&mut contract);
+        register_account(&mut contract, &accounts(0));
+
+        let mut room_config = ft_room_config();
+        room_config.player_limit = Some(MAX_FT_ROOM_PLAYERS + 1);
+        let message = FtTransferMessage::CreateRoom {
+            room_config: Box::new(room_config),
+        };
+        ft_transfer_call(&mut contract, &accounts(0), ENTRY_FEE, message);
+    }
+
+    #[test]
+    #[should_panic(expected = "The entry fee must be paid with the token")]
+    fn test_join_ft_room_with_near() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+
+        set_context(&accounts(3), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_ft_payout() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0), accounts(2)]);
+        assert_eq!(ft_transfers(), vec![(accounts(1), 3 * ENTRY_FEE)]);
+        assert_eq!(transferred_to(&accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_ft_refund_on_remove() {
+        let mut contract = new_contract();
+        let room_id = setup_ft_room(&mut contract);
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(
+            ft_transfers(),
+            vec![
+                (accounts(0), ENTRY_FEE),
+                (accounts(1), ENTRY_FEE),
+                (accounts(2), ENTRY_FEE)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_ft_transfer_is_claimable() {
+        let mut contract = new_contract();
+        register_account(&mut contract, &accounts(1));
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        contract.on_ft_transfer(token_id(), accounts(1), U128(ENTRY_FEE));
+        assert_eq!(contract.get_ft_claim(accounts(1), token_id()).0, ENTRY_FEE);
+        assert_eq!(contract.internal_get_account(&accounts(1)).used_bytes, 0);
+
+        set_context(&accounts(1), 0);
+        contract.claim_ft(token_id());
+        assert_eq!(ft_transfers(), vec![(accounts(1), ENTRY_FEE)]);
+        assert_eq!(contract.get_ft_claim(accounts(1), token_id()).0, 0);
+    }
+
+    #[test]
+    fn test_failed_ft_transfer_to_unregistered_account() {
+        let mut contract = new_contract();
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        contract.on_ft_transfer(token_id(), accounts(1), U128(ENTRY_FEE));
+        contract.on_ft_transfer(token_id(), accounts(1), U128(ENTRY_FEE));
+        assert_eq!(
+            contract.get_ft_claim(accounts(1), token_id()).0,
+            2 * ENTRY_FEE
+        );
+        assert!(contract.internal_get_account_opt(&accounts(1)).is_none());
+
+        set_context(&accounts(1), 0);
+        contract.claim_ft(token_id());
+        assert_eq!(ft_transfers(), vec![(accounts(1), 2 * ENTRY_FEE)]);
+    }
+
+    #[test]
+    fn test_successful_ft_transfer_is_not_claimable() {
+        let mut contract = new_contract();
+        register_account(&mut contract, &accounts(1));
+
+        set_promise_results(vec![PromiseResult::Successful(Vec::new())]);
+        contract.on_ft_transfer(token_id(), accounts(1), U128(ENTRY_FEE));
+        assert_eq!(contract.get_ft_claim(accounts(1), token_id()).0, 0);
+    }
+}
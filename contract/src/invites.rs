@@ -0,0 +1,334 @@
+use crate::*;
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Invite {
+    pub room_id: RoomId,
+    pub app_name: AppName,
+    pub inviter_id: AccountId,
+}
+
+impl Contract {
+    pub(crate) fn internal_get_invite(&self, room_id: &RoomId, account_id: &AccountId) -> &Invite {
+        self.invites
+            .get(account_id)
+            .and_then(|invites| invites.iter().find(|invite| invite.room_id.eq(room_id)))
+            .expect("Invite not found")
+    }
+
+    /// Removes the pending invite from the room and from the invites of the account.
+    pub(crate) fn internal_remove_invite(
+        &mut self,
+        room_id: &RoomId,
+        account_id: &AccountId,
+    ) -> Invite {
+        let invites = self.invites.get_mut(account_id).expect("Invite not found");
+        let invite_idx = invites
+            .iter()
+            .position(|invite| invite.room_id.eq(room_id))
+            .expect("Invite not found");
+        let invite = invites.remove(invite_idx);
+        if invites.is_empty() {
+            self.invites.remove(account_id);
+        }
+
+        if let Some(room) = self.rooms.get_mut(room_id) {
+            room.invited_players.retain(|x| x.ne(account_id));
+        }
+
+        invite
+    }
+
+    /// Removes the pending invite and returns its storage to the inviter.
+    pub(crate) fn internal_remove_invite_with_refund(
+        &mut self,
+        room_id: &RoomId,
+        account_id: &AccountId,
+    ) -> Invite {
+        let inviter_id = self
+            .internal_get_invite(room_id, account_id)
+            .inviter_id
+            .clone();
+        let fill_ratio = self.rooms.get(room_id).map(|room| room.fill_ratio());
+        let mut account = self.internal_get_account(&inviter_id);
+        account.start_storage_tracker();
+
+        let invite = self.internal_remove_invite(room_id, account_id);
+
+        self.rooms.flush();
+        self.invites.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&inviter_id, account);
+        if let Some(fill_ratio) = fill_ratio {
+            self.internal_update_room_fill(room_id, fill_ratio);
+        }
+
+        invite
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Invites the account to the room. The invite reserves a seat in the room and lets the
+    /// account join the hidden room without the access code. Accepts the `Lobby` rooms.
+    #[payable]
+    pub fn invite(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let inviter_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+
+        room.assert_permission(&inviter_id, Permission::Invite);
+        room.assert_status(&[RoomStatus::Lobby]);
+        if room.players.contains(&account_id) {
+            panic!("The player is already joined")
+        }
+        if room.invited_players.contains(&account_id) {
+            panic!("The account is already invited")
+        }
+        if room.is_banned(&account_id) {
+            panic!("Player is banned")
+        }
+        if room.player_limit <= room.players.len() + room.invited_players.len() {
+            panic!("Player limit exceeded")
+        }
+        let invite = Invite {
+            room_id,
+            app_name: room.app_name.clone(),
+            inviter_id: inviter_id.clone(),
+        };
+        let fill_ratio = room.fill_ratio();
+
+        let mut account = self.internal_unwrap_account_or_create(&inviter_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.invited_players.push(account_id.clone());
+        self.invites.entry(account_id).or_default().push(invite);
+
+        self.rooms.flush();
+        self.invites.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&inviter_id, account);
+        self.internal_update_room_fill(&room_id, fill_ratio);
+    }
+
+    pub fn revoke_invite(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::RevokeInvite);
+
+        self.internal_remove_invite_with_refund(&room_id, &account_id);
+    }
+
+    /// Joins the room the account was invited to.
+    #[payable]
+    pub fn accept_invite(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        let player_id = predecessor_account_id();
+        let invite = self.internal_remove_invite_with_refund(&room_id, &player_id);
+        let fill_ratio = self.rooms.get(&room_id).unwrap().fill_ratio();
+
+        let storage_deposit = self.internal_collect_entry_fee(&room_id, attached_deposit());
+        let mut account = self.internal_unwrap_account_or_create(&player_id, storage_deposit);
+        account.start_storage_tracker();
+
+        self.internal_add_player(&room_id, &invite.app_name, &player_id);
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&player_id, account);
+        self.internal_update_room_fill(&room_id, fill_ratio);
+    }
+
+    pub fn decline_invite(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        self.internal_remove_invite_with_refund(&room_id, &predecessor_account_id());
+    }
+
+    pub fn get_invites_for_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Invite> {
+        let invites = match self.invites.get(&account_id) {
+            None => return Vec::new(),
+            Some(invites) => invites,
+        };
+        let start = from_index.map(|from_index| from_index.0).unwrap_or(0);
+
+        invites
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(invites.len() as u64) as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    fn setup_invite_room(contract: &mut Contract, is_hidden: bool, player_limit: usize) -> RoomId {
+        let mut config = room_config(APP_NAME, player_limit);
+        config.is_hidden = is_hidden;
+        setup_app_room(contract, app_config(), config, 1)
+    }
+
+    fn invite(contract: &mut Contract, room_id: RoomId, account_id: AccountId) {
+        set_context(&accounts(0), 0);
+        contract.invite(room_id, account_id);
+    }
+
+    #[test]
+    fn test_accept_invite_to_hidden_room() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, true, 3);
+        invite(&mut contract, room_id, accounts(1));
+
+        let invites = contract.get_invites_for_account(accounts(1), None, None);
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].room_id, room_id);
+        assert_eq!(invites[0].inviter_id, accounts(0));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.accept_invite(room_id);
+
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.players, vec![accounts(0), accounts(1)]);
+        assert!(room.invited_players.is_empty());
+        assert!(contract
+            .get_invites_for_account(accounts(1), None, None)
+            .is_empty());
+        assert_eq!(
+            contract
+                .get_app_account_room(APP_NAME.to_string(), accounts(1))
+                .unwrap()
+                .room_id,
+            room_id
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Player limit exceeded")]
+    fn test_invite_reserves_seat() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 2);
+        invite(&mut contract, room_id, accounts(1));
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Player limit exceeded")]
+    fn test_invite_over_player_limit() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 2);
+        invite(&mut contract, room_id, accounts(1));
+        invite(&mut contract, room_id, accounts(2));
+    }
+
+    #[test]
+    fn test_revoke_invite_refunds_inviter() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 3);
+        let used_bytes = contract.internal_get_account(&accounts(0)).used_bytes;
+
+        invite(&mut contract, room_id, accounts(1));
+        assert!(contract.internal_get_account(&accounts(0)).used_bytes > used_bytes);
+
+        contract.revoke_invite(room_id, accounts(1));
+        assert_eq!(
+            contract.internal_get_account(&accounts(0)).used_bytes,
+            used_bytes
+        );
+        assert!(contract
+            .rooms
+            .get(&room_id)
+            .unwrap()
+            .invited_players
+            .is_empty());
+    }
+
+    #[test]
+    fn test_decline_invite_refunds_inviter() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 3);
+        let used_bytes = contract.internal_get_account(&accounts(0)).used_bytes;
+        invite(&mut contract, room_id, accounts(1));
+
+        set_context(&accounts(1), 0);
+        contract.decline_invite(room_id);
+        assert_eq!(
+            contract.internal_get_account(&accounts(0)).used_bytes,
+            used_bytes
+        );
+        assert!(contract
+            .get_invites_for_account(accounts(1), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can invite the players")]
+    fn test_invite_not_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 3);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.invite(room_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is already invited")]
+    fn test_invite_twice() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 3);
+        invite(&mut contract, room_id, accounts(1));
+        invite(&mut contract, room_id, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invite not found")]
+    fn test_accept_without_invite() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, true, 3);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.accept_invite(room_id);
+    }
+
+    #[test]
+    fn test_remove_room_clears_invites() {
+        let mut contract = new_contract();
+        let room_id = setup_invite_room(&mut contract, false, 3);
+        invite(&mut contract, room_id, accounts(1));
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract
+            .get_invites_for_account(accounts(1), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_invites_for_account_pagination() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        register_app(&mut contract, &accounts(5), "other_app");
+
+        set_context(&accounts(0), DEPOSIT);
+        let first_room_id = contract.create_room(room_config(APP_NAME, 3));
+        let second_room_id = contract.create_room(room_config("other_app", 3));
+        invite(&mut contract, first_room_id, accounts(1));
+        invite(&mut contract, second_room_id, accounts(1));
+
+        let invites = contract.get_invites_for_account(accounts(1), Some(U128(1)), Some(1));
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].room_id, second_room_id);
+        assert_eq!(invites[0].app_name, "other_app".to_string());
+    }
+}
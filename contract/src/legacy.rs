@@ -0,0 +1,97 @@
+//! The app indexes as they were stored before they moved to `store::LookupMap`, kept to measure
+//! the current indexes against. The functions are the index paths of the previous contract.
+
+use crate::KeyStore::{AppRooms, RoomsPerAccount, RoomsPerApp, RoomsPerAppAccount};
+use crate::{AppName, RoomId};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::store::{LookupMap, UnorderedSet};
+use near_sdk::AccountId;
+
+pub struct Contract {
+    available_rooms_per_app: UnorderedMap<AppName, UnorderedSet<RoomId>>,
+    rooms_per_app_account: UnorderedMap<AppName, LookupMap<AccountId, Option<RoomId>>>,
+}
+
+impl Default for Contract {
+    fn default() -> Self {
+        Self {
+            available_rooms_per_app: UnorderedMap::new(RoomsPerApp),
+            rooms_per_app_account: UnorderedMap::new(RoomsPerAppAccount),
+        }
+    }
+}
+
+impl Contract {
+    pub fn save_new_room(&mut self, room_id: RoomId, app_name: &AppName, account_id: &AccountId) {
+        let room_id_hash = room_id.to_le_bytes();
+        let hash = near_sdk::env::sha256_array(
+            [account_id.as_bytes(), &room_id_hash[..]]
+                .concat()
+                .as_slice(),
+        );
+
+        let mut rooms_per_account = self
+            .rooms_per_app_account
+            .get(app_name)
+            .unwrap_or_else(|| LookupMap::new(RoomsPerAccount { hash }));
+
+        if rooms_per_account.get(account_id).is_some() {
+            panic!("You are already in the room")
+        }
+
+        rooms_per_account.insert(account_id.clone(), Some(room_id));
+        rooms_per_account.flush();
+        self.rooms_per_app_account
+            .insert(app_name, &rooms_per_account);
+
+        let mut rooms_per_app = self
+            .available_rooms_per_app
+            .get(app_name)
+            .unwrap_or_else(|| UnorderedSet::new(AppRooms { hash }));
+
+        rooms_per_app.insert(room_id);
+        rooms_per_app.flush();
+        self.available_rooms_per_app
+            .insert(app_name, &rooms_per_app);
+    }
+
+    pub fn join(&mut self, room_id: RoomId, app_name: AppName, player_id: AccountId) {
+        let mut room_per_account = self
+            .rooms_per_app_account
+            .get(&app_name)
+            .expect("App not found");
+
+        room_per_account.insert(player_id, Some(room_id));
+        room_per_account.flush();
+        self.rooms_per_app_account
+            .insert(&app_name, &room_per_account);
+    }
+
+    pub fn open(&mut self, room_id: RoomId, app_name: AppName) {
+        let mut available_rooms = self
+            .available_rooms_per_app
+            .get(&app_name)
+            .expect("Available rooms not found in the app");
+
+        available_rooms.insert(room_id);
+        available_rooms.flush();
+
+        self.available_rooms_per_app
+            .insert(&app_name, &available_rooms);
+    }
+
+    pub fn remove_room_from_available(&mut self, room_id: &RoomId, app_name: &AppName) {
+        let mut available_rooms = self
+            .available_rooms_per_app
+            .get(app_name)
+            .expect("Available rooms not found in the app");
+
+        if !available_rooms.remove(room_id) {
+            panic!("Room not found in the app");
+        }
+        available_rooms.flush();
+
+        self.available_rooms_per_app
+            .insert(app_name, &available_rooms);
+    }
+}
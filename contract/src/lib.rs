@@ -1,20 +1,62 @@
+mod access;
 mod account;
+mod account_rooms;
+mod admin;
+mod app;
+mod app_bans;
+mod attestation;
+mod bans;
 mod enumerable;
+mod events;
+mod expiry;
+mod ft_fees;
+mod invites;
+#[cfg(test)]
+mod legacy;
+mod lifecycle;
+mod matchmaking;
+mod nft_gate;
+mod ownership;
+mod prize_pool;
+mod rating;
+mod roles;
+mod room_state;
 mod storage_tracker;
+#[cfg(test)]
+mod test_utils;
+mod turns;
+mod upgrade;
 
 use crate::account::Account;
+use crate::account_rooms::AccountRooms;
+use crate::admin::ContractConfig;
+use crate::app::App;
+use crate::app_bans::AppBan;
+use crate::attestation::{ResultAttestation, ResultStatus};
+use crate::bans::Ban;
+use crate::invites::Invite;
+use crate::lifecycle::RoomStatus;
+use crate::matchmaking::QueueEntry;
+use crate::nft_gate::NftGate;
+use crate::rating::{LeaderboardEntry, Rating};
+use crate::roles::Permission;
+use crate::room_state::RoomState;
+use crate::turns::Game;
+use crate::upgrade::{VersionedAccount, VersionedRoom};
 use crate::KeyStore::{
-    Accounts, AppRooms, Rooms, RoomsPerAccount, RoomsPerApp, RoomsPerAppAccount, StorageDeposit,
+    AccountRoomIndex, Accounts, AppBanList, AppBans, AppQueues, AppRatings, AppRegistry,
+    AppRoomIds, AppRooms, AppRoomsByFill, AvailableRooms, FtClaims, Games, Invites, Leaderboards,
+    QueueEntries, Ratings, RoomIdsPerApp, RoomStates, Rooms, RoomsByFill, StorageDeposit,
+    VersionedAccounts,
 };
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::env::{attached_deposit, predecessor_account_id, random_seed};
-use near_sdk::json_types::U128;
+use near_sdk::collections::TreeMap;
+use near_sdk::env::{attached_deposit, block_timestamp, predecessor_account_id, random_seed};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::store::{LookupMap, UnorderedSet};
-use near_sdk::{near_bindgen, AccountId, CryptoHash};
-use near_sdk::{Balance, BorshStorageKey, Promise};
-use near_sys::panic;
+use near_sdk::store::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::{env, near_bindgen, AccountId, CryptoHash};
+use near_sdk::{Balance, BorshStorageKey, PanicOnDefault, Promise, PromiseOrValue};
 
 type RoomId = u64;
 type AppName = String;
@@ -24,13 +66,46 @@ type AppName = String;
 #[serde(crate = "near_sdk::serde")]
 pub struct Room {
     room_id: RoomId,
+    app_name: AppName,
     name: String,
+    /// The tags to search the room by, e.g. `mode:ranked` or `region:eu`.
+    tags: Vec<String>,
     owner_id: AccountId,
     players: Vec<AccountId>,
-    banned_players: Vec<AccountId>,
+    /// The invited accounts. Every pending invite reserves a seat in the room.
+    invited_players: Vec<AccountId>,
+    bans: Vec<Ban>,
+    /// The players who help the owner to run the room, see [`roles`].
+    moderators: Vec<AccountId>,
+    muted_players: Vec<AccountId>,
     player_limit: usize,
     is_hidden: bool,
-    is_closed: bool,
+    /// The sha256 hash of the code that is required to join the hidden room.
+    #[serde(skip)]
+    access_code_hash: Option<CryptoHash>,
+    status: RoomStatus,
+    /// The final ranking of the players, from the winner to the loser.
+    result: Option<Vec<AccountId>>,
+    /// The result attestation progress, see [`attestation`].
+    result_status: ResultStatus,
+    attestations: Vec<ResultAttestation>,
+    /// The entry fee that every player pays on join.
+    entry_fee: U128,
+    /// The token of the entry fee and the prizes. NEAR is used when it's not set.
+    fee_token_id: Option<AccountId>,
+    /// The shares of the prize pool in percent, from the winner down.
+    payout_table: Vec<u32>,
+    /// The players must hold the NFT to join the room when it's set.
+    nft_gate: Option<NftGate>,
+    /// The escrowed entry fees, paid out once the result is reported.
+    prize_pool: U128,
+    /// The block timestamp of the game finish, which starts the refund timeout of the prize pool.
+    /// Stays 0 until then, so setting it doesn't change the storage of the room.
+    finished_at: U64,
+    /// The block timestamp of the room creation.
+    created_at: U64,
+    /// The block timestamp of the last change of the players or the status, see [`expiry`].
+    last_activity: U64,
     extra: Option<String>,
 }
 
@@ -40,269 +115,738 @@ pub struct Room {
 pub struct RoomConfig {
     app_name: String,
     name: String,
+    tags: Option<Vec<String>>,
     is_hidden: bool,
-    player_limit: usize,
+    access_code_hash: Option<Base58CryptoHash>,
+    player_limit: Option<usize>,
+    entry_fee: Option<U128>,
+    /// The entry fee is paid with `ft_transfer_call` of the token when it's set.
+    fee_token_id: Option<AccountId>,
+    /// Defaults to the winner taking the whole prize pool.
+    payout_table: Option<Vec<u32>>,
+    nft_gate: Option<NftGate>,
     extra: Option<String>,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub enum KeyStore {
     Rooms,
-    RoomsPerApp,
+    RoomsPerApp, // deprecated, do not reuse
     Accounts,
     AppRooms { hash: CryptoHash },
-    RoomsPerAppAccount,
-    RoomsPerAccount { hash: CryptoHash },
+    RoomsPerAppAccount,                   // deprecated, do not reuse
+    RoomsPerAccount { hash: CryptoHash }, // deprecated, do not reuse
     StorageDeposit,
+    Apps, // deprecated, do not reuse
+    Invites,
+    QueueEntries,
+    AppQueues,
+    Ratings,
+    AppRatings { hash: CryptoHash },
+    Leaderboards,
+    FtClaims,
+    RoomIdsPerApp,
+    AppRoomIds { hash: CryptoHash },
+    AppBans,
+    AppBanList { hash: CryptoHash },
+    VersionedAccounts,
+    AvailableRooms,
+    AccountRoomsPerApp, // deprecated, do not reuse
+    AppList,            // deprecated, do not reuse
+    AccountRoomIndex,
+    RoomsByFill,
+    AppRoomsByFill { hash: CryptoHash },
+    AppRegistry,
+    RoomStates,
+    Games,
 }
 
 #[near_bindgen]
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
-    rooms: LookupMap<RoomId, Room>,
-    accounts: LookupMap<AccountId, Account>,
-    available_rooms_per_app: UnorderedMap<AppName, UnorderedSet<RoomId>>,
-    rooms_per_app_account: UnorderedMap<AppName, LookupMap<AccountId, Option<RoomId>>>,
+    rooms: LookupMap<RoomId, VersionedRoom>,
+    /// The apps are enumerated by the migrations, see [`upgrade`].
+    apps: UnorderedMap<AppName, App>,
+    accounts: LookupMap<AccountId, VersionedAccount>,
+    /// The accounts stored before the versioning, see [`upgrade`]. They move to `accounts` on
+    /// their next change.
+    legacy_accounts: LookupMap<AccountId, Account>,
+    available_rooms_per_app: LookupMap<AppName, UnorderedSet<RoomId>>,
+    /// Every room of the app, including the hidden and the started ones.
+    rooms_per_app: LookupMap<AppName, UnorderedSet<RoomId>>,
+    /// The available rooms of the app by the fill ratio and the room id, see [`enumerable`].
+    rooms_by_fill: LookupMap<AppName, TreeMap<(u64, RoomId), ()>>,
+    /// The rooms of every account across the apps, see [`account_rooms`].
+    account_rooms: LookupMap<AccountId, AccountRooms>,
+    /// The key/value state of every room, see [`room_state`].
+    room_states: LookupMap<RoomId, RoomState>,
+    /// The turns and the moves of the games, see [`turns`].
+    games: LookupMap<RoomId, Game>,
     storage_deposits: LookupMap<AccountId, Balance>,
+    invites: LookupMap<AccountId, Vec<Invite>>,
+    queue_entries: LookupMap<AccountId, QueueEntry>,
+    app_queues: LookupMap<AppName, Vec<AccountId>>,
+    ratings: LookupMap<AppName, UnorderedMap<AccountId, Rating>>,
+    /// The best ratings of every app, see [`rating`].
+    leaderboards: LookupMap<AppName, Vec<LeaderboardEntry>>,
+    /// The tokens of the failed transfers by the receiver and the token.
+    ft_claims: LookupMap<(AccountId, AccountId), Balance>,
+    app_bans: LookupMap<AppName, UnorderedMap<AccountId, AppBan>>,
     next_room_id: u64,
+    /// The account that can pause the contract and close any room, see [`admin`].
+    owner_id: AccountId,
+    config: ContractConfig,
+    is_paused: bool,
 }
 
-impl Default for Contract {
-    fn default() -> Self {
+#[near_bindgen]
+impl Contract {
+    /// Initializes the contract. The contract account calls it in the same transaction as the
+    /// deploy.
+    #[private]
+    #[init]
+    pub fn new(owner_id: AccountId, config: ContractConfig) -> Self {
         Self {
             rooms: LookupMap::new(Rooms),
-            accounts: LookupMap::new(Accounts),
-            available_rooms_per_app: UnorderedMap::new(RoomsPerApp),
-            rooms_per_app_account: UnorderedMap::new(RoomsPerAppAccount),
+            apps: UnorderedMap::new(AppRegistry),
+            accounts: LookupMap::new(VersionedAccounts),
+            legacy_accounts: LookupMap::new(Accounts),
+            available_rooms_per_app: LookupMap::new(AvailableRooms),
+            rooms_per_app: LookupMap::new(RoomIdsPerApp),
+            rooms_by_fill: LookupMap::new(RoomsByFill),
+            account_rooms: LookupMap::new(AccountRoomIndex),
+            room_states: LookupMap::new(RoomStates),
+            games: LookupMap::new(Games),
             storage_deposits: LookupMap::new(StorageDeposit),
+            invites: LookupMap::new(Invites),
+            queue_entries: LookupMap::new(QueueEntries),
+            app_queues: LookupMap::new(AppQueues),
+            ratings: LookupMap::new(Ratings),
+            leaderboards: LookupMap::new(Leaderboards),
+            ft_claims: LookupMap::new(FtClaims),
+            app_bans: LookupMap::new(AppBans),
             next_room_id: 0,
+            owner_id,
+            config,
+            is_paused: false,
         }
     }
 }
 
+impl Room {
+    /// Returns the reason why the player can't be added to the room, if any.
+    fn check_can_add_player(&self, player_id: &AccountId) -> Result<(), String> {
+        self.check_status(&[RoomStatus::Lobby])?;
+        if self.player_limit <= self.players.len() {
+            return Err("Player limit exceeded".to_string());
+        }
+        if self.players.contains(player_id) {
+            return Err("The player is already joined".to_string());
+        }
+        if self.is_banned(player_id) {
+            return Err("Player is banned".to_string());
+        }
+        Ok(())
+    }
+
+    /// Same as [`Room::check_can_add_player`], but the seats reserved by the invites are taken.
+    pub fn check_can_join(&self, player_id: &AccountId) -> Result<(), String> {
+        if self.player_limit <= self.players.len() + self.invited_players.len() {
+            return Err("Player limit exceeded".to_string());
+        }
+        self.check_can_add_player(player_id)
+    }
+}
+
 #[near_bindgen]
 impl Contract {
     #[payable]
     pub fn create_room(&mut self, room_config: RoomConfig) -> RoomId {
+        self.assert_app_not_paused(&room_config.app_name);
         let account_id = predecessor_account_id();
-        let room_id = self.next_room_id;
+        self.assert_hidden_room_allowed(&room_config);
 
-        let new_room = Room {
-            room_id,
-            name: room_config.name.clone(),
-            owner_id: account_id.clone(),
-            players: vec![account_id.clone()],
-            banned_players: Vec::new(),
-            player_limit: room_config.player_limit.clone(),
-            is_hidden: room_config.is_hidden.clone(),
-            is_closed: false,
-            extra: room_config.extra.clone(),
-        };
+        let entry_fee = room_config.entry_fee.map_or(0, |entry_fee| entry_fee.0);
+        if entry_fee > 0 && room_config.fee_token_id.is_some() {
+            panic!("The entry fee must be paid with the token")
+        }
+        if attached_deposit() < entry_fee {
+            panic!("The attached deposit is less than the entry fee")
+        }
+        let storage_deposit = attached_deposit() - entry_fee;
+        let account = self.internal_unwrap_account_or_create(&account_id, storage_deposit);
+
+        self.internal_create_room(&account_id, account, room_config)
+    }
 
-        let attached_balanced = attached_deposit();
-        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_balanced);
+    fn assert_hidden_room_allowed(&self, room_config: &RoomConfig) {
+        let app = self.internal_get_app(&room_config.app_name);
+        if room_config.is_hidden && !app.allow_hidden_rooms {
+            panic!("Hidden rooms are not allowed in the app")
+        }
+    }
+
+    /// Creates the room owned by the account and charges the account for its storage.
+    /// The entry fee of the owner must be already collected.
+    fn internal_create_room(
+        &mut self,
+        account_id: &AccountId,
+        mut account: Account,
+        room_config: RoomConfig,
+    ) -> RoomId {
+        let new_room = self.internal_new_room(account_id, &room_config);
+        let room_id = new_room.room_id;
+        let is_hidden = new_room.is_hidden;
         account.start_storage_tracker();
 
-        self.save_new_room(new_room, &room_config, &account_id);
+        self.save_new_room(new_room, &room_config, account_id);
         self.next_room_id += 1;
 
+        self.rooms.flush();
         account.stop_storage_tracker();
-        self.internal_set_account(&account_id, account);
+        self.internal_set_account(account_id, account);
 
-        room_id
-    }
+        self.internal_add_room_to_app(&room_id, &room_config.app_name, !is_hidden);
 
-    fn save_new_room(&mut self, new_room: Room, room_config: &RoomConfig, account_id: &AccountId) {
-        let room_id_hash = new_room.room_id.to_le_bytes();
-        let hash = near_sdk::env::sha256_array(
-            [&account_id.as_bytes()[..], &room_id_hash[..]]
-                .concat()
-                .as_slice(),
-        );
+        events::emit_room_created(self.rooms.get(&room_id).unwrap());
 
-        let mut rooms_per_account = self
-            .rooms_per_app_account
-            .get(&room_config.app_name)
-            .unwrap_or_else(|| LookupMap::new(RoomsPerAccount { hash }));
+        room_id
+    }
 
-        if rooms_per_account.get(&account_id.clone()).is_some() {
-            panic!("You are already in the room")
+    /// Checks the config and returns the new room owned by the account, which takes the next
+    /// room id.
+    fn internal_new_room(&self, account_id: &AccountId, room_config: &RoomConfig) -> Room {
+        self.assert_not_app_banned(&room_config.app_name, account_id);
+        let app = self.internal_get_app(&room_config.app_name);
+        if room_config.access_code_hash.is_some() && !room_config.is_hidden {
+            panic!("The access code can be set only for the hidden room")
+        }
+        let payout_table = room_config
+            .payout_table
+            .clone()
+            .unwrap_or_else(|| prize_pool::DEFAULT_PAYOUT_TABLE.to_vec());
+        prize_pool::assert_valid_payout_table(&payout_table);
+        if let Some(nft_gate) = &room_config.nft_gate {
+            nft_gate.assert_valid();
+        }
+        let tags = room_config.tags.clone().unwrap_or_default();
+        enumerable::assert_valid_tags(&tags);
+        let entry_fee = room_config.entry_fee.unwrap_or(U128(0));
+        let player_limit = app.room_player_limit(room_config.player_limit);
+        if let Some(fee_token_id) = &room_config.fee_token_id {
+            if !app.accepted_tokens.contains(fee_token_id) {
+                panic!("The token is not accepted by the app")
+            }
+            if player_limit > ft_fees::MAX_FT_ROOM_PLAYERS {
+                panic!(
+                    "The player limit of the room with the token entry fee exceeds {}",
+                    ft_fees::MAX_FT_ROOM_PLAYERS
+                )
+            }
         }
 
-        rooms_per_account.insert(account_id.clone(), Some(new_room.room_id.clone()));
-
-        self.rooms_per_app_account
-            .insert(&room_config.app_name, &rooms_per_account);
+        Room {
+            room_id: self.next_room_id,
+            app_name: room_config.app_name.clone(),
+            name: room_config.name.clone(),
+            tags,
+            owner_id: account_id.clone(),
+            players: vec![account_id.clone()],
+            invited_players: Vec::new(),
+            bans: Vec::new(),
+            moderators: Vec::new(),
+            muted_players: Vec::new(),
+            player_limit,
+            is_hidden: room_config.is_hidden,
+            access_code_hash: room_config.access_code_hash.map(CryptoHash::from),
+            status: RoomStatus::Lobby,
+            result: None,
+            result_status: ResultStatus::Playing,
+            attestations: Vec::new(),
+            entry_fee,
+            fee_token_id: room_config.fee_token_id.clone(),
+            payout_table,
+            nft_gate: room_config.nft_gate.clone(),
+            prize_pool: entry_fee,
+            finished_at: U64(0),
+            created_at: U64(block_timestamp()),
+            last_activity: U64(block_timestamp()),
+            extra: room_config.extra.clone(),
+        }
+    }
 
-        let mut rooms_per_app = self
-            .available_rooms_per_app
-            .get(&room_config.app_name)
-            .unwrap_or_else(|| UnorderedSet::new(AppRooms { hash }));
+    fn save_new_room(&mut self, new_room: Room, room_config: &RoomConfig, account_id: &AccountId) {
+        self.internal_add_account_room(&room_config.app_name, account_id, new_room.room_id);
 
-        rooms_per_app.insert(new_room.room_id.clone());
-        self.available_rooms_per_app
-            .insert(&room_config.app_name, &rooms_per_app);
+        self.room_states.insert(new_room.room_id, RoomState::new());
+        self.room_states.flush();
 
-        self.rooms.insert(new_room.room_id, new_room);
+        self.rooms.insert(new_room.room_id, new_room.into());
     }
 
+    #[payable]
     pub fn random_join(&mut self, app_name: AppName) -> RoomId {
+        self.assert_app_not_paused(&app_name);
+        self.internal_get_app(&app_name);
         let account_id = predecessor_account_id();
-        let room_per_account = self.rooms_per_app_account.get(&app_name).expect("App not found");
-        let room = room_per_account.get(&account_id);
-        if !room.is_none() && !room.unwrap().is_none() {
-            panic!("Account is already in the room")
+        if let Err(err) = self.check_can_enter_room(&app_name, &account_id) {
+            panic!("{}", err)
         }
 
         let random_room = self.get_random_room(app_name.clone());
-        self.join(random_room.room_id.clone(), app_name);
+        // The join to the NFT-gated room finishes asynchronously, see `join`.
+        let _ = self.join(random_room.room_id, app_name, None);
 
         random_room.room_id
     }
 
-    pub fn join(&mut self, room_id: RoomId, app_name: AppName) {
-        let room = self.rooms.get_mut(&room_id).expect("Room id not found");
-        if room.is_closed {
-            panic!("The room is already closed")
+    /// Joins the room. The join to the NFT-gated room finishes in the callback of the NFT check
+    /// and resolves to whether the player has joined.
+    #[payable]
+    pub fn join(
+        &mut self,
+        room_id: RoomId,
+        app_name: AppName,
+        access_code: Option<String>,
+    ) -> PromiseOrValue<bool> {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        if let Some(nft_gate) = room.nft_gate.clone() {
+            let promise = self.internal_join_gated(room_id, app_name, access_code, nft_gate);
+            return PromiseOrValue::Promise(promise);
         }
 
-        if room.player_limit <= room.players.len() {
-            panic!("Player limit exceeded")
-        }
+        let fill_ratio = room.fill_ratio();
         let player_id = predecessor_account_id();
-        if room.players.contains(&player_id) {
-            panic!("The player is already joined")
+        let storage_deposit = self.internal_collect_entry_fee(&room_id, attached_deposit());
+        let mut account = self.internal_unwrap_account_or_create(&player_id, storage_deposit);
+        account.start_storage_tracker();
+
+        self.internal_join(&room_id, &app_name, &player_id, access_code);
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&player_id, account);
+        self.internal_update_room_fill(&room_id, fill_ratio);
+
+        PromiseOrValue::Value(true)
+    }
+
+    fn internal_join(
+        &mut self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        player_id: &AccountId,
+        access_code: Option<String>,
+    ) {
+        self.internal_get_app(app_name);
+        let room = self.internal_get_app_room(room_id, app_name);
+        room.assert_access_code(access_code);
+        if let Err(err) = room.check_can_join(player_id) {
+            panic!("{}", err)
         }
 
-        for banned_player_id in room.banned_players.iter() {
-            if banned_player_id.eq(&player_id) {
-                panic!("Player is banned")
-            }
+        self.internal_add_player(room_id, app_name, player_id);
+    }
+
+    /// Adds the player to the room without checking the access to the hidden room.
+    fn internal_add_player(&mut self, room_id: &RoomId, app_name: &AppName, player_id: &AccountId) {
+        let room = self.internal_get_app_room(room_id, app_name);
+        if let Err(err) = room.check_can_add_player(player_id) {
+            panic!("{}", err)
         }
+        self.assert_not_app_banned(app_name, player_id);
+
+        self.internal_add_account_room(app_name, player_id, *room_id);
 
-        let mut room_per_account = self
-            .rooms_per_app_account
-            .get(&app_name)
-            .expect("App not found");
+        let room = self.rooms.get_mut(room_id).unwrap();
+        room.players.push(player_id.clone());
+        room.touch();
 
-        room_per_account.insert(player_id.clone(), Some(room_id));
-        self.rooms_per_app_account
-            .insert(&app_name, &room_per_account);
-        room.players.push(player_id);
+        events::emit_player_joined(room, player_id);
     }
 
-    pub fn leave(&mut self, room_id: RoomId, app_name: AppName) {
-        let room = self.rooms.get_mut(&room_id).expect("Room id not found");
-        if room.is_closed {
-            panic!("The room is already closed")
+    /// Returns the room after checking that it belongs to the app.
+    fn internal_get_app_room(&self, room_id: &RoomId, app_name: &AppName) -> &Room {
+        let room = self.rooms.get(room_id).expect("Room id not found");
+        if room.app_name.ne(app_name) {
+            panic!("The room doesn't belong to the app")
         }
+        room
+    }
 
-        let mut room_per_account = self
-            .rooms_per_app_account
-            .get(&app_name)
-            .expect("App not found");
+    fn internal_get_app_room_mut(&mut self, room_id: &RoomId, app_name: &AppName) -> &mut Room {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        if room.app_name.ne(app_name) {
+            panic!("The room doesn't belong to the app")
+        }
+        room
+    }
 
+    /// Accepts the rooms in any status. When the owner leaves, the room passes to the
+    /// longest-present player or is removed if there is none.
+    pub fn leave(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
         let player_leave_id = predecessor_account_id();
-        let mut player_idx = 0;
-        for player_id in room.players.iter() {
-            if player_id.eq(&player_leave_id) {
-                room_per_account.insert(player_id.clone(), None);
-                self.rooms_per_app_account
-                    .insert(&app_name, &room_per_account);
-                room.players.swap_remove(player_idx);
-                return;
-            }
+        let is_owner = room.owner_id.eq(&player_leave_id);
 
-            player_idx += 1;
+        if self.internal_remove_player_with_refund(&room_id, &player_leave_id) {
+            self.internal_refund_entry_fee(&room_id, &player_leave_id);
+            events::emit_player_left(self.rooms.get(&room_id).unwrap(), &player_leave_id);
+
+            if is_owner {
+                self.internal_hand_over_left_room(&room_id, &app_name);
+            }
         }
     }
 
-    pub fn open(&mut self, room_id: RoomId, app_name: AppName) {
-        let player_id = predecessor_account_id();
-        let mut room = self.rooms.get_mut(&room_id).expect("Room id not found");
+    /// Removes the player from the room and returns the storage of their slot to their account.
+    /// The roles of the player are removed as well.
+    fn internal_remove_player_with_refund(
+        &mut self,
+        room_id: &RoomId,
+        player_id: &AccountId,
+    ) -> bool {
+        let fill_ratio = self.rooms.get(room_id).unwrap().fill_ratio();
+        let mut account = self.internal_get_account(player_id);
+        account.start_storage_tracker();
+
+        let is_removed = self.internal_remove_player(room_id, player_id);
 
-        if room.owner_id.ne(&player_id) {
-            panic!("Only the owner can open the room")
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(player_id, account);
+        self.internal_update_room_fill(room_id, fill_ratio);
+
+        if is_removed {
+            self.internal_remove_player_roles(room_id, player_id);
         }
+        is_removed
+    }
 
-        room.is_closed = false;
+    /// Removes the player from the room and from the rooms of their account.
+    /// Returns false if the player is not in the room.
+    fn internal_remove_player(&mut self, room_id: &RoomId, player_id: &AccountId) -> bool {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        let player_idx = match room.players.iter().position(|x| x.eq(player_id)) {
+            None => return false,
+            Some(player_idx) => player_idx,
+        };
+        room.players.remove(player_idx);
+        room.remove_attestation(player_id);
+        room.touch();
 
-        let mut available_rooms = self
-            .available_rooms_per_app
-            .get(&app_name)
-            .expect("Available rooms not found in the app");
+        self.internal_remove_player_state(room_id, player_id);
+        self.internal_remove_account_room(player_id, room_id);
+        true
+    }
 
-        available_rooms.insert(room_id);
+    /// Opens the room for the new players again. Accepts the `Starting` rooms.
+    pub fn open(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::Open);
+        room.assert_status(&[RoomStatus::Starting]);
+
+        self.internal_set_room_status(&room_id, &app_name, RoomStatus::Lobby);
 
-        self.available_rooms_per_app
-            .insert(&app_name, &available_rooms);
+        events::emit_room_opened(self.rooms.get(&room_id).unwrap());
     }
 
-    pub fn close(&mut self, room_id: RoomId, app_name: AppName) {
-        let mut room = self.rooms.get_mut(&room_id).expect("Room id not found");
-        if room.is_closed {
-            panic!("The room is already closed")
+    /// Adds the room to the app indexes. The contract pays for the indexes, since the removed
+    /// entries leave their slots behind, so it's called outside of the storage trackers.
+    pub(crate) fn internal_add_room_to_app(
+        &mut self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        is_available: bool,
+    ) {
+        let rooms_per_app = self.rooms_per_app.get_mut(app_name).unwrap();
+        rooms_per_app.insert(*room_id);
+        rooms_per_app.flush();
+        self.rooms_per_app.flush();
+        if is_available {
+            self.add_room_to_available(room_id, app_name);
         }
+    }
 
-        let player_id = predecessor_account_id();
-
-        if room.owner_id.ne(&player_id) {
-            panic!("Only the owner can close the room")
+    /// The reverse of [`Contract::internal_add_room_to_app`].
+    pub(crate) fn internal_remove_room_from_app(&mut self, room_id: &RoomId, app_name: &AppName) {
+        let rooms_per_app = self.rooms_per_app.get_mut(app_name).unwrap();
+        rooms_per_app.remove(room_id);
+        rooms_per_app.flush();
+        self.rooms_per_app.flush();
+        if self.internal_is_available(room_id, app_name) {
+            self.remove_room_from_available(room_id, app_name);
         }
+    }
+
+    pub(crate) fn add_room_to_available(&mut self, room_id: &RoomId, app_name: &AppName) {
+        let available_rooms = self
+            .available_rooms_per_app
+            .get_mut(app_name)
+            .expect("Available rooms not found in the app");
+        available_rooms.insert(*room_id);
+        available_rooms.flush();
+        self.available_rooms_per_app.flush();
+
+        let fill_ratio = self.rooms.get(room_id).unwrap().fill_ratio();
+        let rooms_by_fill = self.rooms_by_fill.get_mut(app_name).unwrap();
+        rooms_by_fill.insert(&(fill_ratio, *room_id), &());
+        self.rooms_by_fill.flush();
+    }
+
+    /// Closes the room for the new players before the game starts. Accepts the `Lobby` rooms.
+    pub fn close(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::Close);
+        room.assert_status(&[RoomStatus::Lobby]);
 
-        room.is_closed = true;
+        self.internal_set_room_status(&room_id, &app_name, RoomStatus::Starting);
 
-        self.remove_room_from_available(&room_id, &app_name);
+        events::emit_room_closed(self.rooms.get(&room_id).unwrap());
     }
 
-    fn remove_room_from_available(&mut self, room_id: &RoomId, app_name: &AppName) {
-        let mut available_rooms = self
+    pub(crate) fn remove_room_from_available(&mut self, room_id: &RoomId, app_name: &AppName) {
+        let available_rooms = self
             .available_rooms_per_app
-            .get(&app_name)
+            .get_mut(app_name)
             .expect("Available rooms not found in the app");
-
-        if !available_rooms.remove(&room_id) {
+        if !available_rooms.remove(room_id) {
             panic!("Room not found in the app");
         }
+        available_rooms.flush();
+        self.available_rooms_per_app.flush();
 
-        self.available_rooms_per_app
-            .insert(&app_name, &available_rooms);
+        let fill_ratio = self.rooms.get(room_id).unwrap().fill_ratio();
+        let rooms_by_fill = self.rooms_by_fill.get_mut(app_name).unwrap();
+        rooms_by_fill.remove(&(fill_ratio, *room_id));
+        self.rooms_by_fill.flush();
     }
 
+    /// Removes the room and refunds the prize pool to the players. Accepts the `Lobby` and
+    /// `Starting` rooms, and the rest once the prize pool is paid out, so the refund can't
+    /// override the result.
     pub fn remove(&mut self, room_id: RoomId, app_name: AppName) {
-        let room = self.rooms.get(&room_id).expect("Room id not found");
-        let player_id = predecessor_account_id();
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::Remove);
+        room.assert_prize_pool_refundable();
 
-        if room.owner_id.ne(&player_id) {
-            panic!("Only the owner can remove the room")
-        }
+        self.internal_remove_room(&room_id, &app_name);
+    }
 
-        let mut room_per_account = self
-            .rooms_per_app_account
-            .get(&app_name)
-            .expect("App name not found");
+    /// Removes the room with all its players from the app. Every player gets back the storage of
+    /// their slot and their moves, and the owner gets back the storage of the room itself.
+    pub(crate) fn internal_remove_room(&mut self, room_id: &RoomId, app_name: &AppName) {
+        self.internal_refund_prize_pool(room_id);
+        let room = self.rooms.get(room_id).expect("Room id not found").clone();
 
+        for player_id in &room.invited_players {
+            self.internal_remove_invite_with_refund(room_id, player_id);
+        }
         for player_id in &room.players {
-            room_per_account.insert(player_id.clone(), None);
+            self.internal_remove_player_with_refund(room_id, player_id);
         }
-        self.rooms_per_app_account.insert(&app_name, &room_per_account);
-        self.rooms.remove(&room_id);
-        self.remove_room_from_available(&room_id, &app_name);
+        self.internal_remove_moves_with_refund(room_id);
+        self.internal_remove_room_from_app(room_id, app_name);
+
+        let mut account = self.internal_get_account(&room.owner_id);
+        account.start_storage_tracker();
+
+        self.rooms.remove(room_id);
+        self.room_states.remove(room_id);
+        self.room_states.flush();
+        self.games.remove(room_id);
+        self.games.flush();
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&room.owner_id, account);
+
+        events::emit_room_removed(&room);
     }
 
-    pub fn kick_and_ban(&mut self, player_to_ban_id: AccountId, room_id: RoomId) {
-        let room = self.rooms.get_mut(&room_id).expect("Room id not found");
-        if room.is_closed {
-            panic!("The room is already closed")
+    /// Removes the account from every room it is in, declines its invites and takes it out of
+    /// the matchmaking queue. The rooms owned by the account are removed completely, since their
+    /// storage was paid by the account.
+    fn internal_remove_account_from_rooms(&mut self, account_id: &AccountId) {
+        let invites = self.invites.get(account_id).cloned().unwrap_or_default();
+        for invite in invites {
+            self.internal_remove_invite_with_refund(&invite.room_id, account_id);
+        }
+        if self.queue_entries.contains_key(account_id) {
+            self.internal_remove_queue_entry_with_refund(account_id);
         }
 
-        let player_id = predecessor_account_id();
-
-        if room.owner_id.ne(&player_id) {
-            panic!("Only the owner can kick the player")
+        let account_rooms = self
+            .account_rooms
+            .get(account_id)
+            .map(|account_rooms| account_rooms.rooms.clone())
+            .unwrap_or_default();
+
+        for (app_name, room_id) in account_rooms {
+            let is_owner = match self.rooms.get(&room_id) {
+                None => continue,
+                Some(room) => room.owner_id.eq(account_id),
+            };
+
+            if is_owner {
+                self.rooms
+                    .get(&room_id)
+                    .unwrap()
+                    .assert_prize_pool_refundable();
+                self.internal_remove_room(&room_id, &app_name);
+            } else if self.internal_remove_player_with_refund(&room_id, account_id) {
+                self.internal_refund_entry_fee(&room_id, account_id);
+                events::emit_player_left(self.rooms.get(&room_id).unwrap(), account_id);
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::Gas;
+
+    fn used_gas(f: impl FnOnce()) -> Gas {
+        let used_gas = env::used_gas();
+        f();
+        env::used_gas() - used_gas
+    }
+
+    #[test]
+    fn test_create_room_charges_owner() {
+        let mut contract = new_contract();
+        setup_room(&mut contract, 3, 1);
+
+        assert!(used_bytes(&contract, &accounts(0)) > 0);
+    }
+
+    #[test]
+    fn test_join_charges_joiner_and_leave_refunds() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        assert!(used_bytes(&contract, &accounts(1)) > 0);
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+    }
+
+    #[test]
+    fn test_random_join_charges_joiner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), DEPOSIT);
+        assert_eq!(contract.random_join(APP_NAME.to_string()), room_id);
+        assert!(used_bytes(&contract, &accounts(1)) > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_join_without_storage_deposit() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), 0);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_remove_refunds_every_player() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+        assert!(used_bytes(&contract, &accounts(0)) < owner_used_bytes);
+    }
+
+    #[test]
+    fn test_ban_charges_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(0), 0);
+        contract.ban(room_id, accounts(1), None);
+        assert!(used_bytes(&contract, &accounts(1)) < player_used_bytes);
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+    }
 
-        room.players.retain(|x| x.ne(&player_to_ban_id));
-        room.banned_players.push(player_to_ban_id.clone());
+    #[test]
+    fn test_close_and_open_keep_owner_storage() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        // The contract pays for the available rooms of the app.
+        set_context(&accounts(0), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+
+        contract.open(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+    }
+
+    #[test]
+    fn test_gas_add_account_room() {
+        let mut contract = new_contract();
+        setup_room(&mut contract, DEFAULT_PLAYER_LIMIT, 1);
+        let app_name = APP_NAME.to_string();
+        let mut previous = legacy::Contract::default();
+        previous.save_new_room(0, &app_name, &accounts(0));
+
+        let previous_gas = used_gas(|| previous.join(0, app_name.clone(), accounts(1)));
+        let gas = used_gas(|| contract.internal_add_account_room(&app_name, &accounts(1), 0));
+        assert!(
+            gas < previous_gas,
+            "{} is not less than {}",
+            gas.0,
+            previous_gas.0
+        );
+    }
+
+    #[test]
+    fn test_gas_add_room_to_available() {
+        let mut contract = new_contract();
+        setup_room(&mut contract, DEFAULT_PLAYER_LIMIT, 1);
+        let app_name = APP_NAME.to_string();
+        let mut previous = legacy::Contract::default();
+        previous.save_new_room(0, &app_name, &accounts(0));
+        previous.remove_room_from_available(&0, &app_name);
+        contract.remove_room_from_available(&0, &app_name);
+
+        let previous_gas = used_gas(|| previous.open(0, app_name.clone()));
+        let gas = used_gas(|| contract.add_room_to_available(&0, &app_name));
+        assert!(
+            gas < previous_gas,
+            "{} is not less than {}",
+            gas.0,
+            previous_gas.0
+        );
     }
 }
@@ -0,0 +1,363 @@
+use crate::*;
+
+/// The lifecycle of the room:
+/// `Lobby <-> Starting -> InProgress -> Finished -> Archived`, where the room can also go
+/// straight from `Lobby` to `InProgress`. The room with the prize pool can be removed only in
+/// `Lobby` and `Starting`, or once the pool is paid out or refunded, see [`Contract::remove`].
+#[derive(
+    Clone, Copy, PartialEq, Debug, BorshDeserialize, BorshSerialize, Serialize, Deserialize,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomStatus {
+    /// The players can join the room.
+    Lobby,
+    /// The room is closed for the new players while the owner prepares the game.
+    Starting,
+    InProgress,
+    /// The game is over and the result can be reported.
+    Finished,
+    /// The final state. The players can only leave the room.
+    Archived,
+}
+
+impl Room {
+    /// Checks that the room is in one of the accepted statuses.
+    pub fn check_status(&self, accepted: &[RoomStatus]) -> Result<(), String> {
+        if !accepted.contains(&self.status) {
+            return Err(format!(
+                "The room status {:?} doesn't allow the action",
+                self.status
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn assert_status(&self, accepted: &[RoomStatus]) {
+        if let Err(err) = self.check_status(accepted) {
+            panic!("{}", err)
+        }
+    }
+}
+
+impl Contract {
+    /// Moves the room to the new status. The public room stays in the available rooms of the
+    /// app only while it's in the lobby.
+    pub(crate) fn internal_set_room_status(
+        &mut self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        status: RoomStatus,
+    ) {
+        let room = self.internal_get_app_room_mut(room_id, app_name);
+        let was_available = !room.is_hidden && room.status == RoomStatus::Lobby;
+        let is_available = !room.is_hidden && status == RoomStatus::Lobby;
+        room.status = status;
+        room.touch();
+
+        if was_available && !is_available {
+            self.remove_room_from_available(room_id, app_name);
+        }
+        if !was_available && is_available {
+            self.add_room_to_available(room_id, app_name);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Starts the game and fixes the seat order of the turns from the players, see [`turns`].
+    /// Accepts the `Lobby` and `Starting` rooms.
+    pub fn start_game(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::ChangeStatus);
+        room.assert_status(&[RoomStatus::Lobby, RoomStatus::Starting]);
+
+        self.internal_set_room_status(&room_id, &app_name, RoomStatus::InProgress);
+        self.internal_start_turns(&room_id);
+        events::emit_game_started(self.rooms.get(&room_id).unwrap());
+    }
+
+    /// Finishes the game. Accepts the `InProgress` rooms.
+    pub fn finish_game(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::ChangeStatus);
+        room.assert_status(&[RoomStatus::InProgress]);
+
+        self.internal_set_room_status(&room_id, &app_name, RoomStatus::Finished);
+        self.rooms.get_mut(&room_id).unwrap().finished_at = U64(env::block_timestamp());
+        events::emit_game_finished(self.rooms.get(&room_id).unwrap());
+    }
+
+    /// Archives the room. Accepts the `Finished` rooms.
+    pub fn archive(&mut self, room_id: RoomId, app_name: AppName) {
+        self.assert_app_not_paused(&app_name);
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_permission(&predecessor_account_id(), Permission::ChangeStatus);
+        room.assert_status(&[RoomStatus::Finished]);
+
+        self.internal_set_room_status(&room_id, &app_name, RoomStatus::Archived);
+        events::emit_room_archived(self.rooms.get(&room_id).unwrap());
+    }
+
+    pub fn get_room_status(&self, room_id: RoomId) -> RoomStatus {
+        self.rooms.get(&room_id).expect("Room id not found").status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    fn setup_room_with_status(contract: &mut Contract, status: RoomStatus) -> RoomId {
+        let room_id = setup_room(contract, 3, 1);
+        set_context(&accounts(0), 0);
+        match status {
+            RoomStatus::Lobby => {}
+            RoomStatus::Starting => contract.close(room_id, APP_NAME.to_string()),
+            RoomStatus::InProgress => contract.start_game(room_id, APP_NAME.to_string()),
+            RoomStatus::Finished => finish_game(contract, &accounts(0), room_id),
+            RoomStatus::Archived => {
+                finish_game(contract, &accounts(0), room_id);
+                contract.archive(room_id, APP_NAME.to_string());
+            }
+        }
+        assert_eq!(contract.get_room_status(room_id), status);
+        set_context(&accounts(0), 0);
+        room_id
+    }
+
+    #[test]
+    fn test_full_lifecycle() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::Lobby);
+
+        set_context(&accounts(0), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::Starting);
+        contract.open(room_id, APP_NAME.to_string());
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::Lobby);
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            1
+        );
+
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::InProgress);
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"game_started","data":[{"room_id":0,"app_name":"app","owner_id":"alice"}]}"#
+            ]
+        );
+
+        contract.finish_game(room_id, APP_NAME.to_string());
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::Finished);
+        contract.archive(room_id, APP_NAME.to_string());
+        assert_eq!(contract.get_room_status(room_id), RoomStatus::Archived);
+    }
+
+    #[test]
+    fn test_leave_game_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().players,
+            vec![accounts(0)]
+        );
+        assert_eq!(contract.internal_get_account(&accounts(1)).used_bytes, 0);
+    }
+
+    #[test]
+    fn test_leave_archived_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Archived);
+
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can change the room status")]
+    fn test_start_game_not_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+
+        set_context(&accounts(1), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Starting doesn't allow the action")]
+    fn test_close_starting_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Starting);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Lobby doesn't allow the action")]
+    fn test_open_lobby_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Lobby);
+        contract.open(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_open_room_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.open(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_close_room_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_start_game_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.start_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_join_room_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Starting doesn't allow the action")]
+    fn test_join_starting_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Starting);
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Lobby doesn't allow the action")]
+    fn test_finish_game_in_lobby() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Lobby);
+        contract.finish_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Starting doesn't allow the action")]
+    fn test_finish_game_starting() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Starting);
+        contract.finish_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Finished doesn't allow the action")]
+    fn test_start_finished_game() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Finished);
+        contract.start_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Finished doesn't allow the action")]
+    fn test_open_finished_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Finished);
+        contract.open(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_archive_room_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.archive(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Lobby doesn't allow the action")]
+    fn test_archive_room_in_lobby() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Lobby);
+        contract.archive(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Archived doesn't allow the action")]
+    fn test_start_archived_game() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Archived);
+        contract.start_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Archived doesn't allow the action")]
+    fn test_finish_archived_game() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Archived);
+        contract.finish_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Archived doesn't allow the action")]
+    fn test_archive_twice() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Archived);
+        contract.archive(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Archived doesn't allow the action")]
+    fn test_close_archived_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::Archived);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_kick_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.kick(room_id, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_report_result_in_progress() {
+        let mut contract = new_contract();
+        let room_id = setup_room_with_status(&mut contract, RoomStatus::InProgress);
+        contract.report_result(room_id, vec![accounts(0)]);
+    }
+}
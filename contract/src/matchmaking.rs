@@ -0,0 +1,554 @@
+use crate::*;
+use crate::rating::INITIAL_RATING;
+use near_sdk::{env, BlockHeight};
+
+/// The max number of the accounts in the queue of an app, which bounds the scan of the queue.
+/// The apps can set a lower one.
+pub const MAX_QUEUE_SIZE: u32 = 100;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchPreferences {
+    /// The number of players in the room. Defaults to the app player limit.
+    pub room_size: Option<usize>,
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueueEntry {
+    pub app_name: AppName,
+    pub rating: u32,
+    pub room_size: usize,
+    pub enqueued_at: BlockHeight,
+    pub expires_at: BlockHeight,
+}
+
+impl QueueEntry {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= env::block_height()
+    }
+}
+
+impl Contract {
+    /// Puts the account into the matchmaking queue. The caller tracks the storage.
+    fn internal_add_queue_entry(&mut self, account_id: &AccountId, entry: QueueEntry) {
+        self.app_queues
+            .get_mut(&entry.app_name)
+            .expect("App not found")
+            .push(account_id.clone());
+        self.queue_entries.insert(account_id.clone(), entry);
+
+        self.queue_entries.flush();
+        self.app_queues.flush();
+    }
+
+    /// Removes the account from the matchmaking queue and returns its entry. The caller tracks
+    /// the storage.
+    fn internal_remove_queue_entry(&mut self, account_id: &AccountId) -> QueueEntry {
+        let entry = self
+            .queue_entries
+            .remove(account_id)
+            .expect("Account is not in the queue");
+        let queue = self.app_queues.get_mut(&entry.app_name).unwrap();
+        queue.retain(|x| x.ne(account_id));
+
+        self.queue_entries.flush();
+        self.app_queues.flush();
+        entry
+    }
+
+    /// Removes the account from the matchmaking queue and returns the storage to the account.
+    pub(crate) fn internal_remove_queue_entry_with_refund(&mut self, account_id: &AccountId) {
+        let mut account = self.internal_get_account(account_id);
+        account.start_storage_tracker();
+
+        self.internal_remove_queue_entry(account_id);
+
+        account.stop_storage_tracker();
+        self.internal_set_account(account_id, account);
+    }
+
+    /// Creates the room for the matched players and takes them out of the queue. The player who
+    /// waited the longest owns the room and pays for it, the rest pay for their slots. The storage
+    /// is measured while the room is created, so the room is undone when any of the players can't
+    /// pay for their part, and that player is returned.
+    fn internal_create_match(
+        &mut self,
+        app_name: &AppName,
+        room_size: usize,
+        players: &[AccountId],
+    ) -> Result<RoomId, AccountId> {
+        let owner_id = &players[0];
+        let room_config = RoomConfig {
+            app_name: app_name.clone(),
+            name: format!("Match #{}", self.next_room_id),
+            tags: None,
+            is_hidden: true,
+            access_code_hash: None,
+            player_limit: Some(room_size),
+            entry_fee: None,
+            fee_token_id: None,
+            payout_table: None,
+            nft_gate: None,
+            extra: None,
+        };
+        let new_room = self.internal_new_room(owner_id, &room_config);
+        let room_id = new_room.room_id;
+
+        let mut accounts = Vec::new();
+        let mut entries = Vec::new();
+        for (idx, player_id) in players.iter().enumerate() {
+            let mut account = self.internal_get_account(player_id);
+            account.start_storage_tracker();
+            entries.push(self.internal_remove_queue_entry(player_id));
+            if idx == 0 {
+                self.save_new_room(new_room.clone(), &room_config, owner_id);
+            } else {
+                self.internal_add_account_room(app_name, player_id, room_id);
+                self.rooms
+                    .get_mut(&room_id)
+                    .unwrap()
+                    .players
+                    .push(player_id.clone());
+            }
+            self.rooms.flush();
+            account.stop_storage_tracker();
+            accounts.push(account);
+        }
+
+        let unpaid_idx = accounts
+            .iter()
+            .position(|account| !account.is_tracked_storage_covered());
+        if unpaid_idx.is_some() {
+            for ((player_id, account), entry) in
+                players.iter().zip(&mut accounts).zip(entries).rev()
+            {
+                account.start_storage_tracker();
+                self.internal_remove_account_room(player_id, &room_id);
+                if player_id.eq(owner_id) {
+                    self.room_states.remove(&room_id);
+                    self.room_states.flush();
+                    self.rooms.remove(&room_id);
+                } else {
+                    self.rooms.get_mut(&room_id).unwrap().players.pop();
+                }
+                self.rooms.flush();
+                self.internal_add_queue_entry(player_id, entry);
+                account.stop_storage_tracker();
+            }
+        }
+        for (player_id, account) in players.iter().zip(accounts) {
+            self.internal_set_account(player_id, account);
+        }
+        if let Some(unpaid_idx) = unpaid_idx {
+            return Err(players[unpaid_idx].clone());
+        }
+
+        self.next_room_id += 1;
+        self.internal_add_room_to_app(&room_id, app_name, false);
+        let room = self.rooms.get(&room_id).unwrap();
+        events::emit_room_created(&new_room);
+        for player_id in &players[1..] {
+            events::emit_player_joined(room, player_id);
+        }
+        Ok(room_id)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Puts the account into the matchmaking queue of the app. The account is matched by its
+    /// rating in the app.
+    #[payable]
+    pub fn enqueue(&mut self, app_name: AppName, preferences: Option<MatchPreferences>) {
+        self.assert_app_not_paused(&app_name);
+        let account_id = predecessor_account_id();
+        let rating = self
+            .get_rating(app_name.clone(), account_id.clone())
+            .map_or(INITIAL_RATING, |rating| rating.rating);
+        let app = self.internal_get_app(&app_name);
+        let room_size = app.room_player_limit(preferences.and_then(|x| x.room_size));
+        if room_size < 2 {
+            panic!("The room size must be at least 2")
+        }
+        let block_height = env::block_height();
+        let entry = QueueEntry {
+            app_name: app_name.clone(),
+            rating,
+            room_size,
+            enqueued_at: block_height,
+            expires_at: block_height + app.queue_ttl_blocks,
+        };
+        let max_queue_size = app.max_queue_size as usize;
+
+        if let Err(err) = self.check_can_enter_room(&app_name, &account_id) {
+            panic!("{}", err)
+        }
+        self.assert_not_app_banned(&app_name, &account_id);
+        if let Some(queue_entry) = self.queue_entries.get(&account_id) {
+            if !queue_entry.is_expired() {
+                panic!("Account is already in the queue")
+            }
+            self.internal_remove_queue_entry_with_refund(&account_id);
+        }
+
+        let queue = self.app_queues.get(&app_name).expect("App not found");
+        if queue.len() >= max_queue_size {
+            panic!("The matchmaking queue is full")
+        }
+
+        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_deposit());
+        account.start_storage_tracker();
+
+        self.internal_add_queue_entry(&account_id, entry);
+
+        account.stop_storage_tracker();
+        self.internal_set_account(&account_id, account);
+    }
+
+    pub fn dequeue(&mut self) {
+        self.assert_not_paused();
+        self.internal_remove_queue_entry_with_refund(&predecessor_account_id());
+    }
+
+    /// Groups the queued players with close ratings and the same room size into the new rooms.
+    /// Anyone can call it. The expired queue entries are removed, as well as the entries of the
+    /// accounts that can't enter the room or pay for their part of it.
+    pub fn match_players(&mut self, app_name: AppName, max_matches: u32) -> Vec<RoomId> {
+        self.assert_app_not_paused(&app_name);
+        let rating_window = self.internal_get_app(&app_name).rating_window;
+        let queue = self.app_queues.get(&app_name).cloned().unwrap_or_default();
+
+        let mut candidates: Vec<(AccountId, QueueEntry)> = Vec::new();
+        for account_id in queue {
+            let entry = self.queue_entries.get(&account_id).unwrap().clone();
+            match self.internal_get_account_opt(&account_id) {
+                // The account has unregistered, so there is nobody to refund.
+                None => {
+                    self.internal_remove_queue_entry(&account_id);
+                }
+                Some(_)
+                    if entry.is_expired()
+                        || self.check_can_enter_room(&app_name, &account_id).is_err()
+                        || self.check_not_app_banned(&app_name, &account_id).is_err() =>
+                {
+                    self.internal_remove_queue_entry_with_refund(&account_id)
+                }
+                Some(_) => candidates.push((account_id, entry)),
+            }
+        }
+        candidates.sort_by_key(|(_, entry)| (entry.room_size, entry.rating, entry.enqueued_at));
+
+        let mut room_ids = Vec::new();
+        let mut idx = 0;
+        while idx < candidates.len() && room_ids.len() < max_matches as usize {
+            let first = &candidates[idx].1;
+            let last_idx = idx + first.room_size - 1;
+            let is_match = candidates.get(last_idx).is_some_and(|(_, last)| {
+                last.room_size == first.room_size && last.rating - first.rating <= rating_window
+            });
+            if !is_match {
+                idx += 1;
+                continue;
+            }
+
+            let mut players = candidates[idx..=last_idx].to_vec();
+            players.sort_by_key(|(_, entry)| entry.enqueued_at);
+            let room_size = first.room_size;
+            let players: Vec<AccountId> = players
+                .into_iter()
+                .map(|(account_id, _)| account_id)
+                .collect();
+            match self.internal_create_match(&app_name, room_size, &players) {
+                Ok(room_id) => {
+                    room_ids.push(room_id);
+                    idx = last_idx + 1;
+                }
+                // The group is tried again without the player who can't pay.
+                Err(player_id) => {
+                    self.internal_remove_queue_entry_with_refund(&player_id);
+                    candidates.retain(|(account_id, _)| account_id.ne(&player_id));
+                }
+            }
+        }
+
+        room_ids
+    }
+
+    pub fn get_queue_entry(&self, account_id: AccountId) -> Option<QueueEntry> {
+        self.queue_entries.get(&account_id).cloned()
+    }
+
+    pub fn get_queue_size(&self, app_name: AppName) -> usize {
+        self.app_queues
+            .get(&app_name)
+            .map_or(0, |queue| queue.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppConfig;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+
+    fn enqueue(contract: &mut Contract, account_id: AccountId, rating: u32, room_size: usize) {
+        contract
+            .ratings
+            .get_mut(&APP_NAME.to_string())
+            .unwrap()
+            .insert(
+                account_id.clone(),
+                Rating {
+                    rating,
+                    games_played: 1,
+                },
+            );
+        set_context(&account_id, DEPOSIT);
+        contract.enqueue(
+            APP_NAME.to_string(),
+            Some(MatchPreferences {
+                room_size: Some(room_size),
+            }),
+        );
+    }
+
+    fn account_room_id(contract: &Contract, account_id: AccountId) -> Option<RoomId> {
+        contract
+            .get_app_account_room(APP_NAME.to_string(), account_id)
+            .map(|room| room.room_id)
+    }
+
+    #[test]
+    fn test_match_players_with_close_ratings() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1500, 2);
+        enqueue(&mut contract, accounts(2), 1050, 2);
+        enqueue(&mut contract, accounts(3), 1550, 2);
+        assert_eq!(contract.get_queue_size(APP_NAME.to_string()), 4);
+
+        set_context(&accounts(4), 0);
+        let room_ids = contract.match_players(APP_NAME.to_string(), 10);
+        assert_eq!(room_ids.len(), 2);
+        assert_eq!(contract.get_queue_size(APP_NAME.to_string()), 0);
+
+        assert_eq!(account_room_id(&contract, accounts(0)), Some(room_ids[0]));
+        assert_eq!(account_room_id(&contract, accounts(2)), Some(room_ids[0]));
+        assert_eq!(account_room_id(&contract, accounts(1)), Some(room_ids[1]));
+        assert_eq!(account_room_id(&contract, accounts(3)), Some(room_ids[1]));
+
+        let room = contract.rooms.get(&room_ids[0]).unwrap();
+        assert_eq!(room.owner_id, accounts(0));
+        assert_eq!(room.player_limit, 2);
+        assert!(room.is_hidden);
+    }
+
+    #[test]
+    fn test_match_players_outside_rating_window() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1200, 2);
+
+        set_context(&accounts(4), 0);
+        assert!(contract.match_players(APP_NAME.to_string(), 10).is_empty());
+        assert_eq!(contract.get_queue_size(APP_NAME.to_string()), 2);
+    }
+
+    #[test]
+    fn test_match_players_groups_by_room_size() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1000, 3);
+        enqueue(&mut contract, accounts(2), 1000, 3);
+
+        set_context(&accounts(4), 0);
+        assert!(contract.match_players(APP_NAME.to_string(), 10).is_empty());
+
+        enqueue(&mut contract, accounts(3), 1000, 3);
+        set_context(&accounts(4), 0);
+        let room_ids = contract.match_players(APP_NAME.to_string(), 10);
+        assert_eq!(room_ids.len(), 1);
+        assert_eq!(
+            contract.rooms.get(&room_ids[0]).unwrap().players,
+            vec![accounts(1), accounts(2), accounts(3)]
+        );
+        assert!(contract.get_queue_entry(accounts(0)).is_some());
+    }
+
+    #[test]
+    fn test_match_players_max_matches() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        for idx in 0..4 {
+            enqueue(&mut contract, accounts(idx), 1000, 2);
+        }
+
+        set_context(&accounts(4), 0);
+        assert_eq!(contract.match_players(APP_NAME.to_string(), 1).len(), 1);
+        assert_eq!(contract.get_queue_size(APP_NAME.to_string()), 2);
+    }
+
+    #[test]
+    fn test_expired_queue_entries_are_removed() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        let queue_ttl_blocks = contract
+            .internal_get_app(&APP_NAME.to_string())
+            .queue_ttl_blocks;
+
+        set_context_at_block(&accounts(1), DEPOSIT, queue_ttl_blocks);
+        contract.enqueue(
+            APP_NAME.to_string(),
+            Some(MatchPreferences { room_size: Some(2) }),
+        );
+        assert!(contract.match_players(APP_NAME.to_string(), 10).is_empty());
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+        assert_eq!(contract.internal_get_account(&accounts(0)).used_bytes, 0);
+    }
+
+    #[test]
+    fn test_dequeue_refunds_storage() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        assert!(contract.internal_get_account(&accounts(0)).used_bytes > 0);
+
+        set_context(&accounts(0), 0);
+        contract.dequeue();
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+        assert_eq!(contract.internal_get_account(&accounts(0)).used_bytes, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the queue")]
+    fn test_enqueue_twice() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the room")]
+    fn test_enqueue_while_in_room() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        enqueue(&mut contract, accounts(0), 1000, 2);
+    }
+
+    #[test]
+    fn test_enqueue_with_stored_rating() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        contract.enqueue(APP_NAME.to_string(), None);
+        assert_eq!(
+            contract.get_queue_entry(accounts(0)).unwrap().rating,
+            INITIAL_RATING
+        );
+
+        enqueue(&mut contract, accounts(1), 1200, 2);
+        assert_eq!(contract.get_queue_entry(accounts(1)).unwrap().rating, 1200);
+    }
+
+    #[test]
+    fn test_match_players_drops_account_without_balance() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1000, 2);
+        enqueue(&mut contract, accounts(2), 1000, 2);
+
+        set_context(&accounts(0), 1);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(4), 0);
+        let room_ids = contract.match_players(APP_NAME.to_string(), 10);
+        assert_eq!(room_ids.len(), 1);
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+        assert_eq!(account_room_id(&contract, accounts(0)), None);
+        assert_eq!(account_room_id(&contract, accounts(1)), Some(room_ids[0]));
+        assert_eq!(account_room_id(&contract, accounts(2)), Some(room_ids[0]));
+    }
+
+    #[test]
+    fn test_match_players_undoes_room_of_owner_without_balance() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1000, 2);
+        let used_bytes = contract.internal_get_account(&accounts(1)).used_bytes;
+        let next_room_id = contract.next_room_id;
+
+        set_context(&accounts(0), 1);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(4), 0);
+        assert!(contract.match_players(APP_NAME.to_string(), 10).is_empty());
+        assert_eq!(contract.next_room_id, next_room_id);
+        assert!(contract.rooms.get(&next_room_id).is_none());
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+        assert_eq!(contract.internal_get_account(&accounts(0)).used_bytes, 0);
+        assert!(contract.get_queue_entry(accounts(1)).is_some());
+        assert_eq!(account_room_id(&contract, accounts(1)), None);
+        assert_eq!(
+            contract.internal_get_account(&accounts(1)).used_bytes,
+            used_bytes
+        );
+    }
+
+    #[test]
+    fn test_storage_unregister_removes_queue_entry() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        enqueue(&mut contract, accounts(0), 1000, 2);
+        enqueue(&mut contract, accounts(1), 1000, 2);
+
+        set_context(&accounts(0), 1);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.get_queue_entry(accounts(0)).is_none());
+        assert_eq!(contract.get_queue_size(APP_NAME.to_string()), 1);
+
+        set_context(&accounts(4), 0);
+        assert!(contract.match_players(APP_NAME.to_string(), 10).is_empty());
+        assert!(contract.get_queue_entry(accounts(1)).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "The matchmaking queue is full")]
+    fn test_enqueue_into_full_queue() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        for idx in 0..=MAX_QUEUE_SIZE {
+            let account_id: AccountId = format!("player{}.near", idx).parse().unwrap();
+            enqueue(&mut contract, account_id, 1000, 2);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The matchmaking queue is full")]
+    fn test_enqueue_into_full_app_queue() {
+        let mut contract = new_contract();
+        set_context(&accounts(5), DEPOSIT);
+        contract.register_app(
+            APP_NAME.to_string(),
+            AppConfig {
+                max_queue_size: Some(2),
+                ..app_config()
+            },
+        );
+        for idx in 0..3 {
+            enqueue(&mut contract, accounts(idx), 1000, 2);
+        }
+    }
+}
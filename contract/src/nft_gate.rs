@@ -0,0 +1,554 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_sdk::{env, ext_contract, log, require, serde_json, Gas, PromiseResult};
+
+const GAS_FOR_NFT_TOKENS_FOR_OWNER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_NFT_TOKEN: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_ON_NFT_GATE_CHECK: Gas = Gas(20_000_000_000_000);
+/// The max number of the accepted token ids, each of them is checked with its own call.
+const MAX_GATE_TOKEN_IDS: usize = 5;
+
+/// The core and the enumeration extension of the NFT standard, called on the collection
+/// contract.
+#[allow(dead_code)]
+#[ext_contract(ext_nft)]
+pub trait NonFungibleToken {
+    fn nft_token(&self, token_id: TokenId) -> Option<Token>;
+
+    fn nft_tokens_for_owner(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Token>;
+}
+
+/// Requires the players to hold a token of the collection to join the room.
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftGate {
+    pub contract_id: AccountId,
+    /// Any token of the collection is accepted when not set.
+    pub token_ids: Option<Vec<TokenId>>,
+}
+
+impl NftGate {
+    pub fn assert_valid(&self) {
+        if let Some(token_ids) = &self.token_ids {
+            require!(
+                !token_ids.is_empty() && token_ids.len() <= MAX_GATE_TOKEN_IDS,
+                format!(
+                    "The NFT gate must have from 1 to {} token ids",
+                    MAX_GATE_TOKEN_IDS
+                )
+            );
+        }
+    }
+
+    /// Requests the tokens of the player. The explicit token ids are looked up one by one,
+    /// otherwise the first page of the tokens of the player is enough.
+    fn check_tokens(&self, player_id: &AccountId) -> Promise {
+        let token_ids = match &self.token_ids {
+            None => {
+                return ext_nft::ext(self.contract_id.clone())
+                    .with_static_gas(GAS_FOR_NFT_TOKENS_FOR_OWNER)
+                    .nft_tokens_for_owner(player_id.clone(), None, Some(1))
+            }
+            Some(token_ids) => token_ids,
+        };
+        let nft_token = |token_id: &TokenId| {
+            ext_nft::ext(self.contract_id.clone())
+                .with_static_gas(GAS_FOR_NFT_TOKEN)
+                .nft_token(token_id.clone())
+        };
+        token_ids[1..]
+            .iter()
+            .fold(nft_token(&token_ids[0]), |promise, token_id| {
+                promise.and(nft_token(token_id))
+            })
+    }
+
+    /// Returns the tokens from the results of [`NftGate::check_tokens`], or the error if any of
+    /// the calls failed.
+    fn tokens_from_results(&self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        for idx in 0..env::promise_results_count() {
+            let result = match env::promise_result(idx) {
+                PromiseResult::Successful(result) => result,
+                _ => return Err("Failed to check the NFT of the player".to_string()),
+            };
+            let parsed = if self.token_ids.is_none() {
+                serde_json::from_slice::<Vec<Token>>(&result)
+            } else {
+                serde_json::from_slice::<Option<Token>>(&result).map(Vec::from_iter)
+            };
+            match parsed {
+                Ok(result_tokens) => tokens.extend(result_tokens),
+                Err(_) => return Err("Failed to check the NFT of the player".to_string()),
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn is_passed(&self, tokens: &[Token], player_id: &AccountId) -> bool {
+        tokens.iter().any(|token| {
+            token.owner_id.eq(player_id)
+                && self
+                    .token_ids
+                    .as_ref()
+                    .is_none_or(|token_ids| token_ids.contains(&token.token_id))
+        })
+    }
+}
+
+impl Contract {
+    /// Starts the join to the NFT-gated room. The player is added to the room by the callback
+    /// only if they hold the token. The entry fee is kept aside until then. Every check of the
+    /// join runs before the NFT check as well, so the fee is rarely sent back.
+    pub(crate) fn internal_join_gated(
+        &mut self,
+        room_id: RoomId,
+        app_name: AppName,
+        access_code: Option<String>,
+        nft_gate: NftGate,
+    ) -> Promise {
+        let player_id = predecessor_account_id();
+        let room = self.internal_get_app_room(&room_id, &app_name);
+        room.assert_access_code(access_code);
+        let entry_fee = room.near_entry_fee(attached_deposit());
+
+        let account =
+            self.internal_unwrap_account_or_create(&player_id, attached_deposit() - entry_fee);
+        self.internal_set_account(&player_id, account);
+        if let Err(err) = self.check_can_join_gated(&room_id, &app_name, &player_id) {
+            panic!("{}", err)
+        }
+
+        nft_gate.check_tokens(&player_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_ON_NFT_GATE_CHECK)
+                .on_nft_gate_check(room_id, app_name, player_id, U128(entry_fee)),
+        )
+    }
+
+    /// Returns the reason why the player can't join the NFT-gated room, if any. It's checked
+    /// both before and after the NFT check, since the room could change in between.
+    fn check_can_join_gated(
+        &self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        player_id: &AccountId,
+    ) -> Result<(), String> {
+        let room = match self.rooms.get(room_id) {
+            Some(room) if room.app_name.eq(app_name) => room,
+            _ => return Err("Room id not found".to_string()),
+        };
+        room.check_can_join(player_id)?;
+        self.check_not_app_banned(app_name, player_id)?;
+        self.check_can_enter_room(app_name, player_id)?;
+        if self.internal_get_account_opt(player_id).is_none() {
+            return Err("Account not found".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the reason why the player can't join the room after the NFT check, if any.
+    fn internal_check_gated_join(
+        &self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        player_id: &AccountId,
+    ) -> Result<(), String> {
+        self.check_can_join_gated(room_id, app_name, player_id)?;
+        let nft_gate = self.rooms.get(room_id).unwrap().nft_gate.as_ref();
+        let nft_gate = nft_gate.ok_or_else(|| "The room is not NFT-gated".to_string())?;
+        if !nft_gate.is_passed(&nft_gate.tokens_from_results()?, player_id) {
+            return Err("The player doesn't hold the required NFT".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Finishes the join to the NFT-gated room. Returns the entry fee to the player and returns
+    /// false if the player can't join the room. It never fails, so the fee is always either
+    /// added to the prize pool or sent back. The storage of the join is measured, and the join is
+    /// undone when the storage balance of the player doesn't cover it.
+    #[private]
+    pub fn on_nft_gate_check(
+        &mut self,
+        room_id: RoomId,
+        app_name: AppName,
+        player_id: AccountId,
+        entry_fee: U128,
+    ) -> bool {
+        if let Err(err) = self.internal_check_gated_join(&room_id, &app_name, &player_id) {
+            log!("{} can't join the room {}: {}", player_id, room_id, err);
+            if entry_fee.0 > 0 {
+                Promise::new(player_id).transfer(entry_fee.0);
+            }
+            return false;
+        }
+
+        let fill_ratio = self.rooms.get(&room_id).unwrap().fill_ratio();
+        let mut account = self.internal_get_account(&player_id);
+        account.start_storage_tracker();
+
+        self.internal_add_account_room(&app_name, &player_id, room_id);
+        self.rooms
+            .get_mut(&room_id)
+            .unwrap()
+            .players
+            .push(player_id.clone());
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        if !account.is_tracked_storage_covered() {
+            account.start_storage_tracker();
+            self.internal_remove_account_room(&player_id, &room_id);
+            self.rooms.get_mut(&room_id).unwrap().players.pop();
+            self.rooms.flush();
+            account.stop_storage_tracker();
+            self.internal_set_account(&player_id, account);
+
+            log!(
+                "{} can't join the room {}: Not enough storage balance",
+                player_id,
+                room_id
+            );
+            if entry_fee.0 > 0 {
+                Promise::new(player_id).transfer(entry_fee.0);
+            }
+            return false;
+        }
+        self.internal_set_account(&player_id, account);
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.prize_pool.0 += entry_fee.0;
+        room.touch();
+        events::emit_player_joined(room, &player_id);
+        self.internal_update_room_fill(&room_id, fill_ratio);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+
+    const ENTRY_FEE: Balance = 1_000;
+
+    fn nft_contract_id() -> AccountId {
+        "nft.near".parse().unwrap()
+    }
+
+    fn token(token_id: &str, owner_id: AccountId) -> Token {
+        Token {
+            token_id: token_id.to_string(),
+            owner_id,
+            metadata: None,
+            approved_account_ids: None,
+        }
+    }
+
+    /// Returns the result of `nft_tokens_for_owner`.
+    fn tokens_result(tokens: Vec<Token>) -> PromiseResult {
+        PromiseResult::Successful(serde_json::to_vec(&tokens).unwrap())
+    }
+
+    /// Returns the result of `nft_token`.
+    fn token_result(token: Option<Token>) -> PromiseResult {
+        PromiseResult::Successful(serde_json::to_vec(&token).unwrap())
+    }
+
+    fn setup_gated_room(contract: &mut Contract, token_ids: Option<Vec<TokenId>>) -> RoomId {
+        let mut config = room_config(APP_NAME, 2);
+        config.entry_fee = Some(U128(ENTRY_FEE));
+        config.nft_gate = Some(NftGate {
+            contract_id: nft_contract_id(),
+            token_ids,
+        });
+        setup_app_room(contract, app_config(), config, 1)
+    }
+
+    fn join(contract: &mut Contract, room_id: RoomId, account_id: AccountId) {
+        set_context(&account_id, DEPOSIT + ENTRY_FEE);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    fn on_nft_gate_check(
+        contract: &mut Contract,
+        promise_results: Vec<PromiseResult>,
+        room_id: RoomId,
+        player_id: AccountId,
+    ) -> bool {
+        set_promise_results(promise_results);
+        contract.on_nft_gate_check(room_id, APP_NAME.to_string(), player_id, U128(ENTRY_FEE))
+    }
+
+    fn players(contract: &Contract, room_id: RoomId) -> Vec<AccountId> {
+        contract.rooms.get(&room_id).unwrap().players.clone()
+    }
+
+    /// Returns the names of the methods called on the NFT contract.
+    fn nft_calls() -> Vec<String> {
+        get_created_receipts()
+            .iter()
+            .filter(|receipt| receipt.receiver_id.eq(&nft_contract_id()))
+            .flat_map(|receipt| receipt.actions.iter())
+            .filter_map(|action| match action {
+                VmAction::FunctionCall { function_name, .. } => Some(function_name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_join_requests_nft_check() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert_eq!(nft_calls(), vec!["nft_tokens_for_owner".to_string()]);
+        assert_eq!(
+            contract.internal_get_account(&accounts(1)).storage_balance,
+            DEPOSIT
+        );
+    }
+
+    #[test]
+    fn test_join_requests_every_token_id() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(
+            &mut contract,
+            Some(vec!["gold".to_string(), "silver".to_string()]),
+        );
+        join(&mut contract, room_id, accounts(1));
+
+        assert_eq!(
+            nft_calls(),
+            vec!["nft_token".to_string(), "nft_token".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_join_with_nft() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        let results = vec![tokens_result(vec![token("1", accounts(1))])];
+        assert!(on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0), accounts(1)]);
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().prize_pool.0,
+            2 * ENTRY_FEE
+        );
+        assert!(used_bytes(&contract, &accounts(1)) > 0);
+    }
+
+    #[test]
+    fn test_join_without_nft() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            vec![tokens_result(Vec::new())],
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().prize_pool.0,
+            ENTRY_FEE
+        );
+    }
+
+    #[test]
+    fn test_join_with_token_id() {
+        let mut contract = new_contract();
+        let token_ids = vec!["gold".to_string(), "silver".to_string()];
+        let room_id = setup_gated_room(&mut contract, Some(token_ids));
+        join(&mut contract, room_id, accounts(1));
+
+        let results = vec![
+            token_result(Some(token("gold", accounts(2)))),
+            token_result(None),
+        ];
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+
+        join(&mut contract, room_id, accounts(1));
+        let results = vec![
+            token_result(Some(token("gold", accounts(2)))),
+            token_result(Some(token("silver", accounts(1)))),
+        ];
+        assert!(on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+    }
+
+    #[test]
+    fn test_failed_nft_check() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            vec![PromiseResult::Failed],
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_room_filled_during_nft_check() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+        join(&mut contract, room_id, accounts(2));
+
+        let results = vec![tokens_result(vec![token("1", accounts(2))])];
+        assert!(on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(2)
+        ));
+
+        let results = vec![tokens_result(vec![token("2", accounts(1))])];
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0), accounts(2)]);
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_room_removed_during_nft_check() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+
+        let results = vec![tokens_result(vec![token("1", accounts(1))])];
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_storage_withdrawn_during_nft_check() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        join(&mut contract, room_id, accounts(1));
+
+        set_context(&accounts(1), 1);
+        contract.storage_withdraw(None);
+
+        let results = vec![tokens_result(vec![token("1", accounts(1))])];
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_join_without_storage_balance() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        set_context(&accounts(1), DEPOSIT);
+        contract.storage_deposit(None, None);
+        set_context(&accounts(1), 1);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(1), ENTRY_FEE);
+        contract.join(room_id, APP_NAME.to_string(), None);
+
+        let results = vec![tokens_result(vec![token("1", accounts(1))])];
+        assert!(!on_nft_gate_check(
+            &mut contract,
+            results,
+            room_id,
+            accounts(1)
+        ));
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(1))
+            .is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is already in the room")]
+    fn test_join_while_in_other_room() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+        set_context(&accounts(1), DEPOSIT);
+        contract.create_room(room_config(APP_NAME, 2));
+
+        join(&mut contract, room_id, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The NFT gate must have from 1 to 5 token ids")]
+    fn test_create_room_with_too_many_token_ids() {
+        let mut contract = new_contract();
+        let token_ids = (0..=MAX_GATE_TOKEN_IDS)
+            .map(|idx| idx.to_string())
+            .collect();
+        setup_gated_room(&mut contract, Some(token_ids));
+    }
+
+    #[test]
+    fn test_random_join_gated_room() {
+        let mut contract = new_contract();
+        let room_id = setup_gated_room(&mut contract, None);
+
+        set_context(&accounts(1), DEPOSIT + ENTRY_FEE);
+        assert_eq!(contract.random_join(APP_NAME.to_string()), room_id);
+        assert_eq!(players(&contract, room_id), vec![accounts(0)]);
+        assert!(get_created_receipts()
+            .iter()
+            .any(|receipt| receipt.receiver_id.eq(&nft_contract_id())));
+    }
+}
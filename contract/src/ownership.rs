@@ -0,0 +1,366 @@
+use crate::*;
+use crate::roles::Permission;
+use crate::turns::Game;
+use near_sdk::env;
+
+impl Room {
+    /// Returns the room without the parts paid by the players: their slots, the invites and the
+    /// result attestations. The rest of the room storage is paid by the owner.
+    fn without_player_storage(&self) -> Room {
+        Room {
+            players: Vec::new(),
+            invited_players: Vec::new(),
+            attestations: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Contract {
+    /// Removes the room record with its state record and its game. Returns the game, if any.
+    /// The room stays in the app indexes, which are paid by the contract.
+    fn internal_take_room_out(&mut self, room_id: &RoomId) -> Option<Game> {
+        self.rooms.remove(room_id);
+        self.room_states.remove(room_id);
+        self.room_states.flush();
+        let game = self.games.remove(room_id);
+        self.games.flush();
+        self.rooms.flush();
+        game
+    }
+
+    /// The reverse of [`Contract::internal_take_room_out`]. The room state is put back empty.
+    fn internal_put_room_back(&mut self, room: Room, game: Option<Game>) {
+        let room_id = room.room_id;
+        self.rooms.insert(room_id, room.into());
+        self.room_states.insert(room_id, RoomState::new());
+        self.room_states.flush();
+        if let Some(game) = game {
+            self.games.insert(room_id, game);
+            self.games.flush();
+        }
+        self.rooms.flush();
+    }
+
+    /// Puts the removed invites back to the invited accounts. The invited accounts stay in the
+    /// room, so only the invites of the accounts are stored.
+    fn internal_put_invites_back(&mut self, invites: Vec<(AccountId, Invite)>) {
+        for (account_id, invite) in invites {
+            self.invites.entry(account_id).or_default().push(invite);
+        }
+        self.invites.flush();
+    }
+
+    /// Hands the room over to the first of the candidates who can pay for the room storage.
+    /// The storage paid by the owner, including the invites of the owner, moves from the previous
+    /// owner to the new one, while the players keep paying for their slots. Returns the new owner,
+    /// if any.
+    fn internal_hand_over_room(
+        &mut self,
+        room_id: &RoomId,
+        app_name: &AppName,
+        candidates: &[AccountId],
+    ) -> Option<AccountId> {
+        let mut room = self.internal_get_app_room(room_id, app_name).clone();
+        let previous_owner_id = room.owner_id.clone();
+        let mut previous_owner = self.internal_get_account(&previous_owner_id);
+        let mut invites: Vec<(AccountId, Invite)> = room
+            .invited_players
+            .iter()
+            .map(|account_id| {
+                let invite = self.internal_get_invite(room_id, account_id);
+                (account_id.clone(), invite.clone())
+            })
+            .filter(|(_, invite)| invite.inviter_id.eq(&previous_owner_id))
+            .collect();
+        let owner_room = Room {
+            invited_players: invites.iter().map(|(x, _)| x.clone()).collect(),
+            ..room.without_player_storage()
+        };
+
+        // The previous owner gets back the storage of the room, including the player slots, the
+        // room state and the moves paid by the players, which they pay for again once the room
+        // is back.
+        previous_owner.start_storage_tracker();
+        self.rooms.insert(*room_id, owner_room.clone().into());
+        let room_state = self.room_states.insert(*room_id, RoomState::new()).unwrap();
+        self.room_states.flush();
+        let moves = self
+            .games
+            .get_mut(room_id)
+            .map(|game| std::mem::take(&mut game.moves));
+        self.games.flush();
+        self.rooms.flush();
+        let storage_usage = env::storage_usage();
+        let game = self.internal_take_room_out(room_id);
+        for (account_id, _) in &invites {
+            self.internal_remove_invite(room_id, account_id);
+        }
+        self.invites.flush();
+        let room_storage_cost =
+            Balance::from(storage_usage - env::storage_usage()) * env::storage_byte_cost();
+        previous_owner.stop_storage_tracker();
+
+        let new_owner_id = candidates
+            .iter()
+            .find(|account_id| {
+                self.internal_get_account_opt(account_id)
+                    .is_some_and(|account| account.available_balance() >= room_storage_cost)
+            })
+            .cloned();
+
+        match &new_owner_id {
+            None => {
+                previous_owner.start_storage_tracker();
+                self.internal_put_room_back(owner_room, game);
+                self.internal_put_invites_back(invites);
+            }
+            Some(new_owner_id) => {
+                room.owner_id = new_owner_id.clone();
+                for (_, invite) in &mut invites {
+                    invite.inviter_id = new_owner_id.clone();
+                }
+                let mut new_owner = self.internal_get_account(new_owner_id);
+                new_owner.start_storage_tracker();
+                self.internal_put_room_back(
+                    Room {
+                        owner_id: new_owner_id.clone(),
+                        ..owner_room
+                    },
+                    game,
+                );
+                self.internal_put_invites_back(invites);
+                new_owner.stop_storage_tracker();
+                self.internal_set_account(new_owner_id, new_owner);
+
+                previous_owner.start_storage_tracker();
+            }
+        }
+        room.touch();
+        self.rooms.insert(*room_id, room.into());
+        self.room_states.insert(*room_id, room_state);
+        self.room_states.flush();
+        if let Some(moves) = moves {
+            self.games.get_mut(room_id).unwrap().moves = moves;
+            self.games.flush();
+        }
+        self.rooms.flush();
+        previous_owner.stop_storage_tracker();
+        self.internal_set_account(&previous_owner_id, previous_owner);
+
+        if new_owner_id.is_some() {
+            events::emit_ownership_transferred(
+                self.rooms.get(room_id).unwrap(),
+                &previous_owner_id,
+            );
+        }
+        new_owner_id
+    }
+
+    /// Passes the room of the owner who has left to the longest-present player who can pay for
+    /// the room storage. The room is removed when there is no such player.
+    pub(crate) fn internal_hand_over_left_room(&mut self, room_id: &RoomId, app_name: &AppName) {
+        let players = self.rooms.get(room_id).unwrap().players.clone();
+        if self
+            .internal_hand_over_room(room_id, app_name, &players)
+            .is_none()
+        {
+            self.internal_remove_room(room_id, app_name);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Transfers the room to another player in the room. The new owner pays for the room storage
+    /// from now on, and the previous owner gets it back.
+    pub fn transfer_ownership(&mut self, room_id: RoomId, new_owner_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::TransferOwnership);
+        if room.owner_id.eq(&new_owner_id) {
+            panic!("The account is already the owner")
+        }
+        if !room.players.contains(&new_owner_id) {
+            panic!("The new owner must be a player in the room")
+        }
+
+        let app_name = room.app_name.clone();
+        if self
+            .internal_hand_over_room(&room_id, &app_name, &[new_owner_id])
+            .is_none()
+        {
+            panic!("The new owner doesn't have enough storage balance")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::ONE_YOCTO;
+
+    fn owner_id(contract: &Contract, room_id: RoomId) -> AccountId {
+        contract.rooms.get(&room_id).unwrap().owner_id.clone()
+    }
+
+    #[test]
+    fn test_transfer_ownership() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(0), 0);
+        contract.transfer_ownership(room_id, accounts(1));
+        assert_eq!(owner_id(&contract, room_id), accounts(1));
+        assert!(used_bytes(&contract, &accounts(0)) < owner_used_bytes);
+        assert!(used_bytes(&contract, &accounts(1)) > player_used_bytes);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"ownership_transferred","data":[{"room_id":0,"app_name":"app","owner_id":"bob","account_id":"alice"}]}"#
+            ]
+        );
+
+        set_context(&accounts(1), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can transfer the ownership")]
+    fn test_transfer_ownership_not_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+
+        set_context(&accounts(1), 0);
+        contract.transfer_ownership(room_id, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The new owner must be a player in the room")]
+    fn test_transfer_ownership_to_stranger() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+
+        set_context(&accounts(0), 0);
+        contract.transfer_ownership(room_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "The new owner doesn't have enough storage balance")]
+    fn test_transfer_ownership_without_storage_balance() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+
+        set_context(&accounts(1), ONE_YOCTO);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(0), 0);
+        contract.transfer_ownership(room_id, accounts(1));
+    }
+
+    #[test]
+    fn test_owner_leave_hands_over_to_longest_present_player() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 4);
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(owner_id(&contract, room_id), accounts(2));
+        assert!(contract
+            .get_app_account_room(APP_NAME.to_string(), accounts(0))
+            .is_none());
+
+        set_context(&accounts(2), 0);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_owner_leave_moves_invites_to_new_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+        set_context(&accounts(0), 0);
+        contract.invite(room_id, accounts(2));
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(0)), 0);
+        assert_eq!(
+            contract.get_invites_for_account(accounts(2), None, None)[0].inviter_id,
+            accounts(1)
+        );
+
+        set_context(&accounts(0), ONE_YOCTO);
+        assert!(contract.storage_unregister(None));
+        set_context(&accounts(1), 0);
+        contract.revoke_invite(room_id, accounts(2));
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_owner_leave_removes_room_with_invites() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+        set_context(&accounts(0), 0);
+        contract.invite(room_id, accounts(2));
+
+        set_context(&accounts(1), ONE_YOCTO);
+        contract.storage_withdraw(None);
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(0)), 0);
+        assert!(contract
+            .get_invites_for_account(accounts(2), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_owner_leave_skips_player_without_storage_balance() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 3);
+
+        set_context(&accounts(1), ONE_YOCTO);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(owner_id(&contract, room_id), accounts(2));
+    }
+
+    #[test]
+    fn test_owner_leave_removes_room_without_owner_candidates() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 2);
+
+        set_context(&accounts(1), ONE_YOCTO);
+        contract.storage_withdraw(None);
+
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_owner_leave_removes_empty_room() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 4, 1);
+
+        set_context(&accounts(0), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+    }
+}
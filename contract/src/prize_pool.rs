@@ -0,0 +1,368 @@
+use crate::*;
+use crate::ft_fees::ft_transfer_with_callback;
+use crate::lifecycle::RoomStatus;
+use near_sdk::{require, Timestamp};
+
+/// The winner takes all by default.
+pub const DEFAULT_PAYOUT_TABLE: [u32; 1] = [100];
+
+/// The prize pool of the finished game can be refunded once the result isn't reported for 3 days.
+pub const PRIZE_POOL_REFUND_TIMEOUT: Timestamp = 3 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Checks the payout table: the shares of the prize pool in percent, from the winner down.
+pub fn assert_valid_payout_table(payout_table: &[u32]) {
+    require!(!payout_table.is_empty(), "The payout table is empty");
+    require!(
+        payout_table.iter().sum::<u32>() == 100,
+        "The payout table must sum up to 100 percent"
+    );
+}
+
+/// Sends the prize in the currency of the entry fee.
+fn transfer_prize(fee_token_id: &Option<AccountId>, receiver_id: &AccountId, amount: Balance) {
+    match fee_token_id {
+        None => {
+            Promise::new(receiver_id.clone()).transfer(amount);
+        }
+        Some(token_id) => ft_transfer_with_callback(token_id, receiver_id, amount),
+    }
+}
+
+/// Returns the shares of the prize pool for the ranked players. The share of the missing places
+/// and the rounding leftovers go to the winner.
+fn payouts(prize_pool: Balance, payout_table: &[u32], ranking: &[AccountId]) -> Vec<Balance> {
+    let mut payouts: Vec<Balance> = ranking
+        .iter()
+        .zip(payout_table)
+        .map(|(_, share)| prize_pool * *share as Balance / 100)
+        .collect();
+    payouts[0] += prize_pool - payouts.iter().sum::<Balance>();
+    payouts
+}
+
+impl Room {
+    /// Returns the entry fee in NEAR, checking that it's covered by the attached deposit.
+    pub fn near_entry_fee(&self, attached_deposit: Balance) -> Balance {
+        let entry_fee = self.entry_fee.0;
+        if entry_fee > 0 && self.fee_token_id.is_some() {
+            panic!("The entry fee must be paid with the token")
+        }
+        require!(
+            attached_deposit >= entry_fee,
+            "The attached deposit is less than the entry fee"
+        );
+        entry_fee
+    }
+
+    /// Checks that the prize pool can be refunded without overriding the result: the game hasn't
+    /// started yet or the pool is already paid out.
+    pub fn assert_prize_pool_refundable(&self) {
+        if self.prize_pool.0 > 0 {
+            self.assert_status(&[RoomStatus::Lobby, RoomStatus::Starting]);
+        }
+    }
+}
+
+impl Contract {
+    /// Takes the entry fee of the room out of the attached deposit and puts it into the prize
+    /// pool. Returns the rest of the deposit, which goes to the storage balance.
+    pub(crate) fn internal_collect_entry_fee(
+        &mut self,
+        room_id: &RoomId,
+        attached_deposit: Balance,
+    ) -> Balance {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        let entry_fee = room.near_entry_fee(attached_deposit);
+
+        room.prize_pool.0 += entry_fee;
+        attached_deposit - entry_fee
+    }
+
+    /// Returns the entry fee to the player who leaves the room before the game starts.
+    pub(crate) fn internal_refund_entry_fee(&mut self, room_id: &RoomId, player_id: &AccountId) {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        let entry_fee = room.entry_fee.0;
+        if entry_fee == 0 || !matches!(room.status, RoomStatus::Lobby | RoomStatus::Starting) {
+            return;
+        }
+
+        room.prize_pool.0 -= entry_fee;
+        transfer_prize(&room.fee_token_id, player_id, entry_fee);
+    }
+
+    /// Splits the prize pool of the room equally between its players, e.g. when the room is
+    /// removed without the result. Before the game starts every player gets their entry fee back.
+    /// The players who left the started game forfeit their entry fees, so the pool can't be
+    /// refunded once every player is gone.
+    pub(crate) fn internal_refund_prize_pool(&mut self, room_id: &RoomId) {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        let prize_pool = room.prize_pool.0;
+        if prize_pool == 0 {
+            return;
+        }
+        require!(
+            !room.players.is_empty(),
+            "The last player can't leave the room with the prize pool"
+        );
+        room.prize_pool.0 = 0;
+
+        let share = prize_pool / room.players.len() as Balance;
+        let leftover = prize_pool - share * room.players.len() as Balance;
+        for (idx, player_id) in room.players.iter().enumerate() {
+            let amount = if idx == 0 { share + leftover } else { share };
+            transfer_prize(&room.fee_token_id, player_id, amount);
+        }
+    }
+
+    /// Pays the prize pool out to the ranked players according to the payout table of the room.
+    pub(crate) fn internal_pay_out_prize_pool(&mut self, room_id: &RoomId, ranking: &[AccountId]) {
+        let room = self.rooms.get_mut(room_id).expect("Room id not found");
+        let prize_pool = room.prize_pool.0;
+        if prize_pool == 0 {
+            return;
+        }
+        room.prize_pool.0 = 0;
+
+        for (player_id, amount) in
+            ranking
+                .iter()
+                .zip(payouts(prize_pool, &room.payout_table, ranking))
+        {
+            if amount > 0 {
+                transfer_prize(&room.fee_token_id, player_id, amount);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Splits the prize pool of the finished game equally between the players when the result
+    /// isn't reported within [`PRIZE_POOL_REFUND_TIMEOUT`]. Anyone can call it. Accepts the
+    /// `Finished` and `Archived` rooms.
+    pub fn refund_prize_pool(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_status(&[RoomStatus::Finished, RoomStatus::Archived]);
+        require!(room.prize_pool.0 > 0, "The room has no prize pool");
+        require!(
+            room.finished_at.0.saturating_add(PRIZE_POOL_REFUND_TIMEOUT) <= env::block_timestamp(),
+            "The result can still be reported"
+        );
+
+        self.internal_refund_prize_pool(&room_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::ONE_NEAR;
+
+    const ENTRY_FEE: Balance = ONE_NEAR;
+
+    fn setup_paid_room(contract: &mut Contract, payout_table: Option<Vec<u32>>) -> RoomId {
+        let mut config = room_config(APP_NAME, 3);
+        config.entry_fee = Some(U128(ENTRY_FEE));
+        config.payout_table = payout_table;
+        setup_app_room(contract, app_config(), config, 3)
+    }
+
+    fn prize_pool(contract: &Contract, room_id: RoomId) -> Balance {
+        contract.rooms.get(&room_id).unwrap().prize_pool.0
+    }
+
+    #[test]
+    fn test_payouts() {
+        let ranking = [accounts(0), accounts(1), accounts(2)];
+        assert_eq!(payouts(100, &[100], &ranking), vec![100]);
+        assert_eq!(payouts(100, &[60, 30, 10], &ranking), vec![60, 30, 10]);
+        assert_eq!(payouts(100, &[60, 30, 10], &ranking[..2]), vec![70, 30]);
+        assert_eq!(payouts(10, &[34, 33, 33], &ranking), vec![4, 3, 3]);
+    }
+
+    #[test]
+    fn test_entry_fee_is_escrowed() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        assert_eq!(prize_pool(&contract, room_id), 3 * ENTRY_FEE);
+        assert_eq!(
+            contract.internal_get_account(&accounts(1)).storage_balance,
+            DEPOSIT
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the entry fee")]
+    fn test_join_without_entry_fee() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(3), ENTRY_FEE - 1);
+        contract.join(room_id, APP_NAME.to_string(), None);
+    }
+
+    #[test]
+    fn test_winner_takes_all() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(2), accounts(0), accounts(1)]);
+        assert_eq!(transferred_to(&accounts(2)), 3 * ENTRY_FEE);
+        assert_eq!(transferred_to(&accounts(0)), 0);
+        assert_eq!(prize_pool(&contract, room_id), 0);
+    }
+
+    #[test]
+    fn test_payout_table() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, Some(vec![60, 30, 10]));
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(2), accounts(0)]);
+        assert_eq!(transferred_to(&accounts(1)), 3 * ENTRY_FEE * 60 / 100);
+        assert_eq!(transferred_to(&accounts(2)), 3 * ENTRY_FEE * 30 / 100);
+        assert_eq!(transferred_to(&accounts(0)), 3 * ENTRY_FEE * 10 / 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "The payout table must sum up to 100 percent")]
+    fn test_invalid_payout_table() {
+        let mut contract = new_contract();
+        setup_paid_room(&mut contract, Some(vec![60, 30]));
+    }
+
+    #[test]
+    fn test_leave_before_start_refunds_entry_fee() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+        assert_eq!(prize_pool(&contract, room_id), 2 * ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_leave_after_start_forfeits_entry_fee() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(transferred_to(&accounts(1)), 0);
+        assert_eq!(prize_pool(&contract, room_id), 3 * ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_remove_before_start_refunds_every_player() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        for idx in 0..3 {
+            assert_eq!(transferred_to(&accounts(idx)), ENTRY_FEE);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_remove_after_start() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+        contract.remove(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_remove_after_payout() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(2), accounts(1), accounts(0)]);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(transferred_to(&accounts(2)), 3 * ENTRY_FEE);
+        assert_eq!(transferred_to(&accounts(0)), 0);
+        assert!(contract.rooms.get(&room_id).is_none());
+    }
+
+    #[test]
+    fn test_kick_refunds_entry_fee() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+
+        set_context(&accounts(0), 0);
+        contract.kick(room_id, accounts(1));
+        assert_eq!(transferred_to(&accounts(1)), ENTRY_FEE);
+        assert_eq!(prize_pool(&contract, room_id), 2 * ENTRY_FEE);
+    }
+
+    #[test]
+    fn test_refund_prize_pool_after_timeout() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context_at_timestamp(&accounts(3), 0, PRIZE_POOL_REFUND_TIMEOUT);
+        contract.refund_prize_pool(room_id);
+        for idx in 0..3 {
+            assert_eq!(transferred_to(&accounts(idx)), ENTRY_FEE);
+        }
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The result can still be reported")]
+    fn test_refund_prize_pool_before_timeout() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        finish_game(&mut contract, &accounts(0), room_id);
+
+        set_context_at_timestamp(&accounts(3), 0, PRIZE_POOL_REFUND_TIMEOUT - 1);
+        contract.refund_prize_pool(room_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "The last player can't leave the room with the prize pool")]
+    fn test_last_player_leaves_after_start() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+
+        for idx in [1, 2, 0] {
+            set_context(&accounts(idx), 0);
+            contract.leave(room_id, APP_NAME.to_string());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status InProgress doesn't allow the action")]
+    fn test_storage_unregister_force_of_owner_after_start() {
+        let mut contract = new_contract();
+        let room_id = setup_paid_room(&mut contract, None);
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+
+        set_context(&accounts(0), 1);
+        contract.storage_unregister(Some(true));
+    }
+}
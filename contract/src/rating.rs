@@ -0,0 +1,406 @@
+use crate::*;
+use crate::attestation::{ranking_hash, ResultStatus};
+use crate::lifecycle::RoomStatus;
+
+pub const INITIAL_RATING: u32 = 1500;
+const ELO_K_FACTOR: f64 = 32.0;
+/// The number of the best players kept in the leaderboard of the app.
+const MAX_LEADERBOARD_SIZE: usize = 100;
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Rating {
+    pub rating: u32,
+    pub games_played: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games_played: 0,
+        }
+    }
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LeaderboardEntry {
+    pub account_id: AccountId,
+    pub rating: u32,
+    pub games_played: u32,
+}
+
+fn expected_score(rating: u32, opponent_rating: u32) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent_rating as f64 - rating as f64) / 400.0))
+}
+
+/// Returns the new ELO ratings of the ranked players. Every player plays against every other
+/// player and the higher ranked player wins. The rating change is averaged over the opponents.
+fn elo_ratings(ratings: &[u32]) -> Vec<u32> {
+    let opponents = (ratings.len() - 1) as f64;
+
+    ratings
+        .iter()
+        .enumerate()
+        .map(|(idx, &rating)| {
+            let score_delta: f64 = ratings
+                .iter()
+                .enumerate()
+                .filter(|(opponent_idx, _)| *opponent_idx != idx)
+                .map(|(opponent_idx, &opponent_rating)| {
+                    let score = if idx < opponent_idx { 1.0 } else { 0.0 };
+                    score - expected_score(rating, opponent_rating)
+                })
+                .sum();
+
+            let new_rating = rating as f64 + ELO_K_FACTOR * score_delta / opponents;
+            new_rating.round().max(0.0) as u32
+        })
+        .collect()
+}
+
+/// Puts the entry into the leaderboard, which is sorted by the rating in descending order. Only
+/// the `MAX_LEADERBOARD_SIZE` best entries are kept.
+pub(crate) fn update_leaderboard(leaderboard: &mut Vec<LeaderboardEntry>, entry: LeaderboardEntry) {
+    leaderboard.retain(|x| x.account_id.ne(&entry.account_id));
+    let idx = leaderboard.partition_point(|x| {
+        x.rating > entry.rating || (x.rating == entry.rating && x.account_id < entry.account_id)
+    });
+    leaderboard.insert(idx, entry);
+    leaderboard.truncate(MAX_LEADERBOARD_SIZE);
+}
+
+impl Room {
+    fn assert_valid_ranking(&self, ranking: &[AccountId]) {
+        let is_valid = ranking.len() >= 2
+            && ranking.len() == self.players.len()
+            && self
+                .players
+                .iter()
+                .all(|player_id| ranking.contains(player_id));
+        if !is_valid {
+            panic!("The ranking must contain every player of the room exactly once")
+        }
+    }
+}
+
+impl Contract {
+    /// Updates the ratings of the ranked players and the leaderboard of the app. The caller tracks
+    /// the storage.
+    fn internal_update_ratings(&mut self, room_id: &RoomId, ranking: &[AccountId]) {
+        let room = self.rooms.get(room_id).unwrap().clone();
+        let app_ratings = self.ratings.get(&room.app_name).expect("App not found");
+
+        let old_ratings: Vec<Rating> = ranking
+            .iter()
+            .map(|account_id| app_ratings.get(account_id).cloned().unwrap_or_default())
+            .collect();
+        let new_ratings = elo_ratings(
+            &old_ratings
+                .iter()
+                .map(|rating| rating.rating)
+                .collect::<Vec<u32>>(),
+        );
+
+        for ((account_id, old_rating), new_rating) in
+            ranking.iter().zip(old_ratings).zip(new_ratings)
+        {
+            let rating = Rating {
+                rating: new_rating,
+                games_played: old_rating.games_played + 1,
+            };
+            update_leaderboard(
+                self.leaderboards.get_mut(&room.app_name).unwrap(),
+                LeaderboardEntry {
+                    account_id: account_id.clone(),
+                    rating: rating.rating,
+                    games_played: rating.games_played,
+                },
+            );
+            self.ratings
+                .get_mut(&room.app_name)
+                .unwrap()
+                .insert(account_id.clone(), rating);
+
+            events::emit_rating_changed(&room, account_id, old_rating.rating, new_rating);
+        }
+
+        self.ratings.get_mut(&room.app_name).unwrap().flush();
+        self.ratings.flush();
+        self.leaderboards.flush();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Reports the ranking of the room players, from the winner to the loser, and updates their
+    /// ratings. Can be called once by the room owner or by the app referee. In the result
+    /// attestation mode the ranking must match the result agreed by the players, and once it is
+    /// finalized anyone can report it. The room owner pays for the result, which is removed with
+    /// the room, and the app owner pays for the ratings and the leaderboard of the app.
+    /// Accepts the `Finished` rooms.
+    pub fn report_result(&mut self, room_id: RoomId, ranking: Vec<AccountId>) {
+        self.assert_room_not_paused(&room_id);
+        let reporter_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let app = self.internal_get_app(&room.app_name);
+        let is_agreed_result = app.result_attestation.is_some()
+            && room.result_status
+                == (ResultStatus::Finalized {
+                    result_hash: ranking_hash(&ranking),
+                });
+
+        if !is_agreed_result
+            && room.owner_id.ne(&reporter_id)
+            && app.referee_id.as_ref() != Some(&reporter_id)
+        {
+            panic!("Only the owner or the referee can report the result")
+        }
+        room.assert_status(&[RoomStatus::Finished]);
+        if room.result.is_some() {
+            panic!("The result is already reported")
+        }
+        room.assert_valid_ranking(&ranking);
+        if app.result_attestation.is_some() && !is_agreed_result {
+            panic!("The ranking doesn't match the agreed result")
+        }
+
+        let owner_id = room.owner_id.clone();
+        let app_owner_id = app.owner_id.clone();
+
+        let mut owner = self.internal_get_account(&owner_id);
+        owner.start_storage_tracker();
+        self.rooms.get_mut(&room_id).unwrap().result = Some(ranking.clone());
+        self.rooms.flush();
+        owner.stop_storage_tracker();
+        self.internal_set_account(&owner_id, owner);
+
+        let mut app_owner = self.internal_get_account(&app_owner_id);
+        app_owner.start_storage_tracker();
+        self.internal_update_ratings(&room_id, &ranking);
+        app_owner.stop_storage_tracker();
+        self.internal_set_account(&app_owner_id, app_owner);
+
+        self.internal_pay_out_prize_pool(&room_id, &ranking);
+    }
+
+    pub fn get_rating(&self, app_name: AppName, account_id: AccountId) -> Option<Rating> {
+        self.ratings
+            .get(&app_name)
+            .and_then(|app_ratings| app_ratings.get(&account_id))
+            .cloned()
+    }
+
+    /// Returns the best players of the app sorted by the rating in descending order. Only the
+    /// `MAX_LEADERBOARD_SIZE` best ratings are kept.
+    pub fn get_leaderboard(
+        &self,
+        app_name: AppName,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<LeaderboardEntry> {
+        let leaderboard = self.leaderboards.get(&app_name).expect("App not found");
+
+        let start = from_index.map(|from_index| from_index.0).unwrap_or(0);
+        let limit = limit.unwrap_or(leaderboard.len() as u64);
+        leaderboard
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    fn setup_finished_room(contract: &mut Contract, players: usize) -> RoomId {
+        let mut config = app_config();
+        config.referee_id = Some(accounts(4));
+        let room_id = setup_app_room(contract, config, room_config(APP_NAME, players), players);
+        finish_game(contract, &accounts(0), room_id);
+        room_id
+    }
+
+    fn rating(contract: &Contract, account_id: AccountId) -> u32 {
+        contract
+            .get_rating(APP_NAME.to_string(), account_id)
+            .unwrap()
+            .rating
+    }
+
+    #[test]
+    fn test_elo_ratings() {
+        assert_eq!(elo_ratings(&[1500, 1500]), vec![1516, 1484]);
+        assert_eq!(elo_ratings(&[1500, 1500, 1500]), vec![1516, 1500, 1484]);
+
+        assert_eq!(elo_ratings(&[1400, 1600]), vec![1424, 1576]);
+    }
+
+    #[test]
+    fn test_report_result_by_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0)]);
+
+        assert_eq!(rating(&contract, accounts(1)), 1516);
+        assert_eq!(rating(&contract, accounts(0)), 1484);
+        assert_eq!(
+            contract
+                .get_rating(APP_NAME.to_string(), accounts(0))
+                .unwrap()
+                .games_played,
+            1
+        );
+        assert_eq!(
+            get_logs()[0],
+            r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"rating_changed","data":[{"room_id":0,"app_name":"app","account_id":"bob","old_rating":1500,"new_rating":1516}]}"#
+        );
+        assert_eq!(get_logs().len(), 2);
+    }
+
+    #[test]
+    fn test_report_result_by_referee() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 3);
+
+        set_context(&accounts(4), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(1), accounts(2)]);
+
+        assert_eq!(rating(&contract, accounts(0)), 1516);
+        assert_eq!(rating(&contract, accounts(1)), 1500);
+        assert_eq!(rating(&contract, accounts(2)), 1484);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or the referee can report the result")]
+    fn test_report_result_by_player() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(1), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The result is already reported")]
+    fn test_report_result_twice() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0)]);
+        contract.report_result(room_id, vec![accounts(0), accounts(1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The ranking must contain every player of the room exactly once")]
+    fn test_report_result_with_duplicates() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The ranking must contain every player of the room exactly once")]
+    fn test_report_result_with_stranger() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(0), accounts(3)]);
+    }
+
+    #[test]
+    fn test_get_leaderboard() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 3);
+
+        set_context(&accounts(0), 0);
+        contract.report_result(room_id, vec![accounts(2), accounts(0), accounts(1)]);
+
+        let leaderboard = contract.get_leaderboard(APP_NAME.to_string(), None, None);
+        let account_ids: Vec<AccountId> = leaderboard
+            .iter()
+            .map(|entry| entry.account_id.clone())
+            .collect();
+        assert_eq!(account_ids, vec![accounts(2), accounts(0), accounts(1)]);
+
+        let page = contract.get_leaderboard(APP_NAME.to_string(), Some(U128(1)), Some(1));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].account_id, accounts(0));
+    }
+
+    #[test]
+    fn test_report_result_with_withdrawn_player() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+
+        set_context(&accounts(1), 1);
+        contract.storage_withdraw(None);
+        assert_eq!(
+            contract
+                .internal_get_account(&accounts(1))
+                .available_balance(),
+            0
+        );
+
+        set_context(&accounts(4), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0)]);
+        assert_eq!(rating(&contract, accounts(1)), 1516);
+    }
+
+    #[test]
+    fn test_report_result_charges_owners() {
+        let mut contract = new_contract();
+        let room_id = setup_finished_room(&mut contract, 2);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+        let app_owner_used_bytes = used_bytes(&contract, &accounts(5));
+
+        set_context(&accounts(4), 0);
+        contract.report_result(room_id, vec![accounts(1), accounts(0)]);
+        assert!(contract.storage_balance_of(accounts(4)).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), player_used_bytes);
+        assert!(used_bytes(&contract, &accounts(5)) > app_owner_used_bytes);
+
+        // The result is removed with the room, which returns its storage to the owner.
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(0)), 0);
+    }
+
+    #[test]
+    fn test_update_leaderboard() {
+        let entry = |idx: usize, rating: u32| LeaderboardEntry {
+            account_id: format!("player{}.near", idx).parse().unwrap(),
+            rating,
+            games_played: 1,
+        };
+        let mut leaderboard = Vec::new();
+        for idx in 0..=MAX_LEADERBOARD_SIZE {
+            update_leaderboard(&mut leaderboard, entry(idx, 1000 + idx as u32));
+        }
+        assert_eq!(leaderboard.len(), MAX_LEADERBOARD_SIZE);
+        assert_eq!(leaderboard[0].rating, 1000 + MAX_LEADERBOARD_SIZE as u32);
+        assert_eq!(leaderboard[MAX_LEADERBOARD_SIZE - 1].rating, 1001);
+
+        update_leaderboard(&mut leaderboard, entry(1, 2000));
+        assert_eq!(leaderboard.len(), MAX_LEADERBOARD_SIZE);
+        assert_eq!(leaderboard[0].rating, 2000);
+        assert_eq!(leaderboard[MAX_LEADERBOARD_SIZE - 1].rating, 1002);
+    }
+}
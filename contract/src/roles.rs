@@ -0,0 +1,428 @@
+use crate::*;
+
+/// The roles of the accounts in the room, from the lowest to the highest.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Role {
+    /// Any account without a role, including the regular players.
+    Guest,
+    /// The co-host appointed by the owner. Acts only while in the room.
+    Moderator,
+    Owner,
+}
+
+/// The actions in the room that require a role.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Permission {
+    Open,
+    Close,
+    Kick,
+    Ban,
+    Mute,
+    Remove,
+    TransferOwnership,
+    ManageModerators,
+    /// Start, finish and archive the game.
+    ChangeStatus,
+    SetAccessCode,
+    Invite,
+    RevokeInvite,
+}
+
+impl Permission {
+    fn description(self) -> &'static str {
+        match self {
+            Permission::Open => "open the room",
+            Permission::Close => "close the room",
+            Permission::Kick => "kick the player",
+            Permission::Ban => "ban the player",
+            Permission::Mute => "mute the player",
+            Permission::Remove => "remove the room",
+            Permission::TransferOwnership => "transfer the ownership",
+            Permission::ManageModerators => "manage the moderators",
+            Permission::ChangeStatus => "change the room status",
+            Permission::SetAccessCode => "set the access code",
+            Permission::Invite => "invite the players",
+            Permission::RevokeInvite => "revoke the invites",
+        }
+    }
+}
+
+impl Role {
+    /// The permission matrix of the room.
+    pub fn is_allowed(self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Moderator => matches!(
+                permission,
+                Permission::Open
+                    | Permission::Close
+                    | Permission::Kick
+                    | Permission::Ban
+                    | Permission::Mute
+            ),
+            Role::Guest => false,
+        }
+    }
+}
+
+impl Room {
+    pub fn role_of(&self, account_id: &AccountId) -> Role {
+        if self.owner_id.eq(account_id) {
+            Role::Owner
+        } else if self.moderators.contains(account_id) && self.players.contains(account_id) {
+            Role::Moderator
+        } else {
+            Role::Guest
+        }
+    }
+
+    /// Checks that the role of the account allows the action in the room.
+    pub fn assert_permission(&self, account_id: &AccountId, permission: Permission) {
+        if self.role_of(account_id).is_allowed(permission) {
+            return;
+        }
+        if Role::Moderator.is_allowed(permission) {
+            panic!(
+                "Only the owner or a moderator can {}",
+                permission.description()
+            )
+        }
+        panic!("Only the owner can {}", permission.description())
+    }
+
+    fn has_roles(&self, account_id: &AccountId) -> bool {
+        self.moderators.contains(account_id) || self.muted_players.contains(account_id)
+    }
+
+    /// Checks that the account can apply the action to the player, which requires a higher role.
+    pub fn assert_outranks(&self, account_id: &AccountId, player_id: &AccountId) {
+        if self.role_of(player_id) >= self.role_of(account_id) {
+            panic!("The player has the same or a higher role")
+        }
+    }
+}
+
+impl Contract {
+    /// Changes the room record on behalf of its owner, who pays for the record storage.
+    pub(crate) fn internal_update_room_as_owner(
+        &mut self,
+        room_id: &RoomId,
+        update: impl FnOnce(&mut Room),
+    ) {
+        let owner_id = self.rooms.get(room_id).unwrap().owner_id.clone();
+        let mut account = self.internal_get_account(&owner_id);
+        account.start_storage_tracker();
+
+        update(self.rooms.get_mut(room_id).unwrap());
+
+        self.rooms.flush();
+        account.stop_storage_tracker();
+        self.internal_set_account(&owner_id, account);
+    }
+
+    /// Takes the moderator role and the mute away from the player who is removed from the room.
+    /// The owner gets back their storage.
+    pub(crate) fn internal_remove_player_roles(&mut self, room_id: &RoomId, player_id: &AccountId) {
+        if !self.rooms.get(room_id).unwrap().has_roles(player_id) {
+            return;
+        }
+
+        self.internal_update_room_as_owner(room_id, |room| {
+            room.moderators.retain(|x| x.ne(player_id));
+            room.muted_players.retain(|x| x.ne(player_id));
+        });
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Appoints the player in the room as a moderator. The moderators can open, close the room,
+    /// kick, ban and mute the players, but can't remove the room or transfer the ownership.
+    pub fn add_moderator(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::ManageModerators);
+        if !room.players.contains(&account_id) {
+            panic!("The moderator must be a player in the room")
+        }
+        if room.role_of(&account_id) != Role::Guest {
+            panic!("The player already has a role")
+        }
+
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.moderators.push(account_id.clone())
+        });
+
+        events::emit_moderator_added(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+
+    pub fn remove_moderator(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::ManageModerators);
+        if !room.moderators.contains(&account_id) {
+            panic!("The account is not a moderator")
+        }
+
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.moderators.retain(|x| x.ne(&account_id))
+        });
+
+        events::emit_moderator_removed(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+
+    /// Mutes the player in the room. The clients hide the messages of the muted players.
+    pub fn mute(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let moderator_id = predecessor_account_id();
+        room.assert_permission(&moderator_id, Permission::Mute);
+        room.assert_outranks(&moderator_id, &account_id);
+        if !room.players.contains(&account_id) {
+            panic!("The account is not a player in the room")
+        }
+        if room.muted_players.contains(&account_id) {
+            panic!("The player is already muted")
+        }
+
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.muted_players.push(account_id.clone())
+        });
+
+        events::emit_player_muted(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+
+    pub fn unmute(&mut self, room_id: RoomId, account_id: AccountId) {
+        self.assert_room_not_paused(&room_id);
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        room.assert_permission(&predecessor_account_id(), Permission::Mute);
+        if !room.muted_players.contains(&account_id) {
+            panic!("The player is not muted")
+        }
+
+        self.internal_update_room_as_owner(&room_id, |room| {
+            room.muted_players.retain(|x| x.ne(&account_id))
+        });
+
+        events::emit_player_unmuted(self.rooms.get(&room_id).unwrap(), &account_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    /// Creates the room of alice with bob as a moderator and charlie as a player.
+    fn setup_moderated_room(contract: &mut Contract) -> RoomId {
+        let room_id = setup_room(contract, 4, 3);
+        set_context(&accounts(0), 0);
+        contract.add_moderator(room_id, accounts(1));
+        room_id
+    }
+
+    #[test]
+    fn test_permission_matrix() {
+        assert!(Role::Owner.is_allowed(Permission::Remove));
+        assert!(Role::Owner.is_allowed(Permission::TransferOwnership));
+        assert!(Role::Moderator.is_allowed(Permission::Kick));
+        assert!(Role::Moderator.is_allowed(Permission::Close));
+        assert!(!Role::Moderator.is_allowed(Permission::Remove));
+        assert!(!Role::Moderator.is_allowed(Permission::TransferOwnership));
+        assert!(!Role::Moderator.is_allowed(Permission::ManageModerators));
+        assert!(!Role::Moderator.is_allowed(Permission::ChangeStatus));
+        assert!(!Role::Moderator.is_allowed(Permission::Invite));
+        assert!(!Role::Guest.is_allowed(Permission::Open));
+    }
+
+    #[test]
+    fn test_add_moderator_charges_owner() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 4));
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(0), 0);
+        contract.add_moderator(room_id, accounts(1));
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.role_of(&accounts(1)), Role::Moderator);
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+        assert_eq!(used_bytes(&contract, &accounts(1)), player_used_bytes);
+
+        contract.remove_moderator(room_id, accounts(1));
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.role_of(&accounts(1)), Role::Guest);
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "The moderator must be a player in the room")]
+    fn test_add_moderator_not_player() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        contract.add_moderator(room_id, accounts(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can manage the moderators")]
+    fn test_moderator_cant_add_moderator() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.add_moderator(room_id, accounts(2));
+    }
+
+    #[test]
+    fn test_moderator_closes_and_opens_room() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.close(room_id, APP_NAME.to_string());
+        contract.open(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a moderator can close the room")]
+    fn test_player_cant_close_room() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(2), 0);
+        contract.close(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_moderator_kicks_player() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let moderator_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(1), 0);
+        contract.ban(room_id, accounts(2), None);
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.players, vec![accounts(0), accounts(1)]);
+        assert!(room.is_banned(&accounts(2)));
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+        assert_eq!(used_bytes(&contract, &accounts(1)), moderator_used_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "The player has the same or a higher role")]
+    fn test_moderator_cant_kick_owner() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.kick(room_id, accounts(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can remove the room")]
+    fn test_moderator_cant_remove_room() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can transfer the ownership")]
+    fn test_moderator_cant_transfer_ownership() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.transfer_ownership(room_id, accounts(1));
+    }
+
+    #[test]
+    fn test_moderator_acts_only_in_room() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        let room = contract.rooms.get(&room_id).unwrap();
+        assert_eq!(room.role_of(&accounts(1)), Role::Guest);
+        assert!(room.moderators.is_empty());
+        assert!(used_bytes(&contract, &accounts(0)) < owner_used_bytes);
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_kicked_player_is_unmuted() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+        set_context(&accounts(0), 0);
+        contract.mute(room_id, accounts(2));
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+
+        contract.kick(room_id, accounts(2));
+        assert!(contract
+            .rooms
+            .get(&room_id)
+            .unwrap()
+            .muted_players
+            .is_empty());
+        assert!(used_bytes(&contract, &accounts(0)) < owner_used_bytes);
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().role_of(&accounts(2)),
+            Role::Guest
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can change the room status")]
+    fn test_moderator_cant_start_game() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+    }
+
+    #[test]
+    fn test_moderator_mutes_player() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.mute(room_id, accounts(2));
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().muted_players,
+            vec![accounts(2)]
+        );
+
+        contract.unmute(room_id, accounts(2));
+        assert!(contract
+            .rooms
+            .get(&room_id)
+            .unwrap()
+            .muted_players
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a moderator can mute the player")]
+    fn test_player_cant_mute() {
+        let mut contract = new_contract();
+        let room_id = setup_moderated_room(&mut contract);
+
+        set_context(&accounts(2), 0);
+        contract.mute(room_id, accounts(1));
+    }
+}
@@ -0,0 +1,458 @@
+use crate::*;
+use near_sdk::require;
+
+pub const DEFAULT_MAX_STATE_KEYS: u32 = 32;
+pub const DEFAULT_MAX_STATE_KEY_LENGTH: u32 = 64;
+pub const DEFAULT_MAX_STATE_VALUE_LENGTH: u32 = 1024;
+
+/// The key/value state of the room. It's bounded by the app limits, so it's stored in one record
+/// like the invites, and every entry is paid by its writer.
+pub type RoomState = Vec<RoomStateEntry>;
+
+/// Who can write the room state key. Only the players in the room can write the state.
+#[derive(
+    Clone, Copy, PartialEq, Debug, BorshDeserialize, BorshSerialize, Serialize, Deserialize,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum StateAccess {
+    /// Only the room owner.
+    Owner,
+    /// Any player in the room.
+    AnyPlayer,
+    /// The player who has written the key first, until they leave the room.
+    KeyOwner,
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomStateConfig {
+    /// The max number of keys in the room. The state is disabled when it's zero.
+    pub max_keys: u32,
+    pub max_key_length: u32,
+    pub max_value_length: u32,
+    /// The access of the keys that are not listed in `key_access`.
+    pub default_access: StateAccess,
+    pub key_access: Vec<(String, StateAccess)>,
+}
+
+impl Default for RoomStateConfig {
+    fn default() -> Self {
+        Self {
+            max_keys: DEFAULT_MAX_STATE_KEYS,
+            max_key_length: DEFAULT_MAX_STATE_KEY_LENGTH,
+            max_value_length: DEFAULT_MAX_STATE_VALUE_LENGTH,
+            default_access: StateAccess::Owner,
+            key_access: Vec::new(),
+        }
+    }
+}
+
+impl RoomStateConfig {
+    pub fn assert_valid(&self) {
+        for (idx, (key, _)) in self.key_access.iter().enumerate() {
+            require!(
+                !key.is_empty() && key.len() <= self.max_key_length as usize,
+                "Invalid room state key"
+            );
+            require!(
+                self.key_access[..idx].iter().all(|(x, _)| x.ne(key)),
+                "Duplicate room state key"
+            );
+        }
+    }
+
+    fn access(&self, key: &str) -> StateAccess {
+        self.key_access
+            .iter()
+            .find(|(x, _)| x.eq(key))
+            .map_or(self.default_access, |(_, access)| *access)
+    }
+}
+
+/// The value of the room state key. The writer pays for its storage.
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoomStateEntry {
+    pub key: String,
+    pub value: String,
+    pub writer_id: AccountId,
+}
+
+impl Room {
+    fn assert_can_write_state(
+        &self,
+        account_id: &AccountId,
+        access: StateAccess,
+        writer_id: Option<&AccountId>,
+    ) {
+        if !self.players.contains(account_id) {
+            panic!("Only the players can write the room state")
+        }
+        match access {
+            StateAccess::Owner => {
+                if self.owner_id.ne(account_id) {
+                    panic!("Only the owner can write the key")
+                }
+            }
+            StateAccess::AnyPlayer => {}
+            StateAccess::KeyOwner => {
+                if writer_id.is_some_and(|writer_id| writer_id.ne(account_id)) {
+                    panic!("The key is owned by another player")
+                }
+            }
+        }
+    }
+}
+
+impl Contract {
+    /// Checks that the account can write the key and returns its current writer, if any.
+    fn internal_check_state_write(
+        &self,
+        room_id: &RoomId,
+        key: &String,
+        account_id: &AccountId,
+    ) -> Option<AccountId> {
+        let room = self.rooms.get(room_id).expect("Room id not found");
+        let config = &self.internal_get_app(&room.app_name).room_state;
+        let room_state = self.room_states.get(room_id).expect("Room state not found");
+        let writer_id = room_state
+            .iter()
+            .find(|entry| entry.key.eq(key))
+            .map(|entry| entry.writer_id.clone());
+
+        room.assert_can_write_state(account_id, config.access(key), writer_id.as_ref());
+        writer_id
+    }
+
+    /// Removes the key without tracking the storage. The caller tracks the storage of the writer.
+    fn internal_remove_state_entry(&mut self, room_id: &RoomId, key: &String) {
+        let room_state = self.room_states.get_mut(room_id).unwrap();
+        let idx = room_state
+            .iter()
+            .position(|entry| entry.key.eq(key))
+            .unwrap();
+        let entry = room_state.remove(idx);
+        self.room_states.flush();
+
+        let room = self.rooms.get(room_id).unwrap();
+        events::emit_room_state_removed(room, &entry.writer_id, key);
+    }
+
+    /// Removes the key and returns its storage to the writer.
+    fn internal_remove_state_entry_with_refund(
+        &mut self,
+        room_id: &RoomId,
+        key: &String,
+        writer_id: &AccountId,
+    ) {
+        let mut account = self.internal_get_account(writer_id);
+        account.start_storage_tracker();
+
+        self.internal_remove_state_entry(room_id, key);
+
+        account.stop_storage_tracker();
+        self.internal_set_account(writer_id, account);
+    }
+
+    /// Removes the keys written by the player who leaves the room. The caller tracks the storage
+    /// of the player.
+    pub(crate) fn internal_remove_player_state(&mut self, room_id: &RoomId, player_id: &AccountId) {
+        let keys: Vec<String> = self
+            .room_states
+            .get(room_id)
+            .expect("Room state not found")
+            .iter()
+            .filter(|entry| entry.writer_id.eq(player_id))
+            .map(|entry| entry.key.clone())
+            .collect();
+
+        for key in keys {
+            self.internal_remove_state_entry(room_id, &key);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Writes the value of the key in the room state. The caller pays for the storage of the
+    /// value. When the key was written by another player, they get back its storage.
+    #[payable]
+    pub fn set_room_state(&mut self, room_id: RoomId, key: String, value: String) {
+        self.assert_room_not_paused(&room_id);
+        let account_id = predecessor_account_id();
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let config = &self.internal_get_app(&room.app_name).room_state;
+        require!(
+            !key.is_empty() && key.len() <= config.max_key_length as usize,
+            "Invalid room state key"
+        );
+        require!(
+            value.len() <= config.max_value_length as usize,
+            "The room state value is too long"
+        );
+
+        let writer_id = self.internal_check_state_write(&room_id, &key, &account_id);
+        match &writer_id {
+            None => {
+                let room_state = self.room_states.get(&room_id).unwrap();
+                require!(
+                    room_state.len() < config.max_keys as usize,
+                    "The room state has too many keys"
+                );
+            }
+            Some(writer_id) if writer_id.ne(&account_id) => {
+                self.internal_remove_state_entry_with_refund(&room_id, &key, writer_id);
+            }
+            Some(_) => {}
+        }
+
+        let mut account = self.internal_unwrap_account_or_create(&account_id, attached_deposit());
+        account.start_storage_tracker();
+
+        let room_state = self.room_states.get_mut(&room_id).unwrap();
+        match room_state.iter_mut().find(|entry| entry.key.eq(&key)) {
+            Some(entry) => entry.value = value,
+            None => room_state.push(RoomStateEntry {
+                key: key.clone(),
+                value,
+                writer_id: account_id.clone(),
+            }),
+        }
+        self.room_states.flush();
+
+        account.stop_storage_tracker();
+        self.internal_set_account(&account_id, account);
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.touch();
+        events::emit_room_state_set(room, &account_id, &key);
+    }
+
+    /// Removes the key from the room state. Anyone who can write the key can remove it, and its
+    /// writer gets back the storage.
+    pub fn remove_room_state(&mut self, room_id: RoomId, key: String) {
+        self.assert_room_not_paused(&room_id);
+        let account_id = predecessor_account_id();
+
+        let writer_id = self
+            .internal_check_state_write(&room_id, &key, &account_id)
+            .expect("Room state key not found");
+        self.internal_remove_state_entry_with_refund(&room_id, &key, &writer_id);
+
+        self.rooms.get_mut(&room_id).unwrap().touch();
+    }
+
+    /// Returns the entries of the keys in the same order.
+    pub fn get_room_state(
+        &self,
+        room_id: RoomId,
+        keys: Vec<String>,
+    ) -> Vec<Option<RoomStateEntry>> {
+        let room_state = self.room_states.get(&room_id).expect("Room id not found");
+
+        keys.iter()
+            .map(|key| room_state.iter().find(|entry| entry.key.eq(key)).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppConfig;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    const KEY: &str = "board";
+
+    fn state_app_config() -> AppConfig {
+        let mut config = app_config();
+        config.room_state = Some(RoomStateConfig {
+            max_keys: 3,
+            max_key_length: 16,
+            max_value_length: 32,
+            default_access: StateAccess::KeyOwner,
+            key_access: vec![
+                (KEY.to_string(), StateAccess::AnyPlayer),
+                ("turn".to_string(), StateAccess::Owner),
+            ],
+        });
+        config
+    }
+
+    fn setup_state_room(contract: &mut Contract) -> RoomId {
+        setup_app_room(contract, state_app_config(), room_config(APP_NAME, 3), 3)
+    }
+
+    fn state_value(contract: &Contract, room_id: RoomId, key: &str) -> Option<String> {
+        contract.get_room_state(room_id, vec![key.to_string()])[0]
+            .as_ref()
+            .map(|entry| entry.value.clone())
+    }
+
+    #[test]
+    fn test_set_room_state() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+        assert_eq!(
+            state_value(&contract, room_id, KEY),
+            Some("x..".to_string())
+        );
+        assert!(used_bytes(&contract, &accounts(1)) > player_used_bytes);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"room_state_set","data":[{"room_id":0,"app_name":"app","account_id":"bob","key":"board"}]}"#
+            ]
+        );
+
+        set_context(&accounts(0), 0);
+        contract.set_room_state(room_id, "turn".to_string(), "bob".to_string());
+        let entries =
+            contract.get_room_state(room_id, vec!["turn".to_string(), "other".to_string()]);
+        assert_eq!(entries[0].as_ref().unwrap().writer_id, accounts(0));
+        assert!(entries[1].is_none());
+    }
+
+    #[test]
+    fn test_overwrite_moves_storage_to_writer() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+        set_context(&accounts(2), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "xo.".to_string());
+
+        assert_eq!(used_bytes(&contract, &accounts(1)), player_used_bytes);
+        let entry = contract.get_room_state(room_id, vec![KEY.to_string()])[0]
+            .clone()
+            .unwrap();
+        assert_eq!(entry.writer_id, accounts(2));
+        assert_eq!(entry.value, "xo.");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can write the key")]
+    fn test_owner_key_by_player() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, "turn".to_string(), "bob".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The key is owned by another player")]
+    fn test_key_owned_by_another_player() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, "hand".to_string(), "3".to_string());
+        set_context(&accounts(2), 0);
+        contract.set_room_state(room_id, "hand".to_string(), "4".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the players can write the room state")]
+    fn test_set_room_state_not_player() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(3), DEPOSIT);
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The room state value is too long")]
+    fn test_set_room_state_value_too_long() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "x".repeat(33));
+    }
+
+    #[test]
+    #[should_panic(expected = "The room state has too many keys")]
+    fn test_set_room_state_too_many_keys() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        for key in ["a", "b", "c", "d"] {
+            contract.set_room_state(room_id, key.to_string(), "1".to_string());
+        }
+    }
+
+    #[test]
+    fn test_remove_room_state() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+        set_context(&accounts(2), 0);
+        contract.remove_room_state(room_id, KEY.to_string());
+        assert!(state_value(&contract, room_id, KEY).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), player_used_bytes);
+    }
+
+    #[test]
+    fn test_leave_removes_player_state() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, "hand".to_string(), "3".to_string());
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(state_value(&contract, room_id, "hand").is_none());
+        assert!(state_value(&contract, room_id, KEY).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+
+        set_context(&accounts(2), 0);
+        contract.set_room_state(room_id, "hand".to_string(), "4".to_string());
+    }
+
+    #[test]
+    fn test_remove_room_refunds_state() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, KEY.to_string(), "x..".to_string());
+        set_context(&accounts(0), 0);
+        contract.set_room_state(room_id, "turn".to_string(), "bob".to_string());
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.room_states.get(&room_id).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_room_state_follows_ownership() {
+        let mut contract = new_contract();
+        let room_id = setup_state_room(&mut contract);
+
+        set_context(&accounts(0), 0);
+        contract.set_room_state(room_id, "turn".to_string(), "bob".to_string());
+        contract.transfer_ownership(room_id, accounts(1));
+        assert_eq!(
+            state_value(&contract, room_id, "turn"),
+            Some("bob".to_string())
+        );
+
+        set_context(&accounts(1), 0);
+        contract.set_room_state(room_id, "turn".to_string(), "charlie".to_string());
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+}
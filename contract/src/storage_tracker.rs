@@ -11,6 +11,9 @@ pub struct StorageTracker {
 /// Safety guard for the storage tracker.
 impl Drop for StorageTracker {
     fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
         assert!(self.is_empty(), "Bug, non-tracked storage change");
     }
 }
@@ -0,0 +1,162 @@
+use crate::*;
+use crate::admin::ContractConfig;
+use crate::app::{AppConfig, AppMetadata};
+use near_sdk::mock::VmAction;
+use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, BlockHeight, PromiseResult, StorageUsage, Timestamp};
+use near_sdk::{RuntimeFeesConfig, VMConfig};
+
+pub const APP_NAME: &str = "app";
+pub const DEPOSIT: Balance = 1_000_000_000_000_000_000_000_000;
+pub const DEFAULT_PLAYER_LIMIT: usize = 4;
+pub const MAX_PLAYER_LIMIT: usize = 10;
+
+/// Initializes the contract owned by its own account.
+pub fn new_contract() -> Contract {
+    Contract::new(env::current_account_id(), ContractConfig::default())
+}
+
+/// Sets up the blockchain context for the call made by the predecessor with the attached deposit.
+pub fn set_context(predecessor_id: &AccountId, attached_deposit: Balance) {
+    set_context_at_block(predecessor_id, attached_deposit, 0);
+}
+
+pub fn set_context_at_block(
+    predecessor_id: &AccountId,
+    attached_deposit: Balance,
+    block_height: BlockHeight,
+) {
+    testing_env!(VMContextBuilder::new()
+        .predecessor_account_id(predecessor_id.clone())
+        .attached_deposit(attached_deposit)
+        .block_index(block_height)
+        .build());
+}
+
+pub fn set_context_at_timestamp(
+    predecessor_id: &AccountId,
+    attached_deposit: Balance,
+    block_timestamp: Timestamp,
+) {
+    testing_env!(VMContextBuilder::new()
+        .predecessor_account_id(predecessor_id.clone())
+        .attached_deposit(attached_deposit)
+        .block_timestamp(block_timestamp)
+        .build());
+}
+
+/// Sets up the context of the callback of the contract with the results of the promises.
+pub fn set_promise_results(promise_results: Vec<PromiseResult>) {
+    testing_env!(
+        VMContextBuilder::new()
+            .current_account_id(accounts(4))
+            .predecessor_account_id(accounts(4))
+            .build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        promise_results,
+    );
+}
+
+pub fn app_config() -> AppConfig {
+    AppConfig {
+        metadata: AppMetadata {
+            title: "App".to_string(),
+            description: None,
+            icon: None,
+        },
+        default_player_limit: DEFAULT_PLAYER_LIMIT,
+        max_player_limit: MAX_PLAYER_LIMIT,
+        allow_hidden_rooms: true,
+        extra_schema: None,
+        queue_ttl_blocks: None,
+        max_queue_size: None,
+        rating_window: None,
+        referee_id: None,
+        result_attestation: None,
+        accepted_tokens: None,
+        room_ttl: None,
+        cleanup_bounty_percent: None,
+        max_rooms_per_account: None,
+        room_state: None,
+        turns: None,
+    }
+}
+
+pub fn register_app(contract: &mut Contract, owner_id: &AccountId, app_name: &str) {
+    set_context(owner_id, DEPOSIT);
+    contract.register_app(app_name.to_string(), app_config());
+}
+
+pub fn room_config(app_name: &str, player_limit: usize) -> RoomConfig {
+    RoomConfig {
+        app_name: app_name.to_string(),
+        name: "room".to_string(),
+        tags: None,
+        is_hidden: false,
+        access_code_hash: None,
+        player_limit: Some(player_limit),
+        entry_fee: None,
+        fee_token_id: None,
+        payout_table: None,
+        nft_gate: None,
+        extra: None,
+    }
+}
+
+/// Registers the app of fargo with the config and creates the room of alice, which the next
+/// `players - 1` accounts join. Every player attaches the entry fee of the room.
+pub fn setup_app_room(
+    contract: &mut Contract,
+    app_config: AppConfig,
+    room_config: RoomConfig,
+    players: usize,
+) -> RoomId {
+    let app_name = room_config.app_name.clone();
+    set_context(&accounts(5), DEPOSIT);
+    contract.register_app(app_name.clone(), app_config);
+
+    let deposit = DEPOSIT + room_config.entry_fee.map_or(0, |entry_fee| entry_fee.0);
+    set_context(&accounts(0), deposit);
+    let room_id = contract.create_room(room_config);
+    for idx in 1..players {
+        set_context(&accounts(idx), deposit);
+        contract.join(room_id, app_name.clone(), None);
+    }
+    room_id
+}
+
+/// Same as [`setup_app_room`] with the default configs of the app and the room.
+pub fn setup_room(contract: &mut Contract, player_limit: usize, players: usize) -> RoomId {
+    setup_app_room(
+        contract,
+        app_config(),
+        room_config(APP_NAME, player_limit),
+        players,
+    )
+}
+
+pub fn used_bytes(contract: &Contract, account_id: &AccountId) -> StorageUsage {
+    contract.internal_get_account(account_id).used_bytes
+}
+
+/// Starts and finishes the game in the room on behalf of the owner.
+pub fn finish_game(contract: &mut Contract, owner_id: &AccountId, room_id: RoomId) {
+    set_context(owner_id, 0);
+    contract.start_game(room_id, APP_NAME.to_string());
+    contract.finish_game(room_id, APP_NAME.to_string());
+}
+
+/// Returns the sum of all transfers to the account created during the last call.
+pub fn transferred_to(account_id: &AccountId) -> Balance {
+    get_created_receipts()
+        .iter()
+        .filter(|receipt| receipt.receiver_id.eq(account_id))
+        .flat_map(|receipt| receipt.actions.iter())
+        .map(|action| match action {
+            VmAction::Transfer { deposit } => *deposit,
+            _ => 0,
+        })
+        .sum()
+}
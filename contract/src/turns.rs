@@ -0,0 +1,511 @@
+use crate::*;
+use near_sdk::{env, require, BlockHeight};
+
+pub const DEFAULT_MAX_MOVES: u32 = 256;
+pub const DEFAULT_MAX_MOVE_LENGTH: u32 = 256;
+
+/// What happens to the player who hasn't moved in time.
+#[derive(
+    Clone, Copy, PartialEq, Debug, BorshDeserialize, BorshSerialize, Serialize, Deserialize,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    /// The turn passes to the next player.
+    Skip,
+    /// The player is out of the turns for the rest of the game.
+    Forfeit,
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TurnConfig {
+    /// The max number of moves in the game log.
+    pub max_moves: u32,
+    pub max_move_length: u32,
+    /// The number of blocks the player has for the move. The turns don't time out when it's not
+    /// set.
+    pub turn_timeout_blocks: Option<BlockHeight>,
+    pub timeout_action: TimeoutAction,
+}
+
+impl Default for TurnConfig {
+    fn default() -> Self {
+        Self {
+            max_moves: DEFAULT_MAX_MOVES,
+            max_move_length: DEFAULT_MAX_MOVE_LENGTH,
+            turn_timeout_blocks: None,
+            timeout_action: TimeoutAction::Skip,
+        }
+    }
+}
+
+impl TurnConfig {
+    pub fn assert_valid(&self) {
+        require!(self.max_moves > 0, "The max moves must be positive");
+        require!(
+            self.turn_timeout_blocks != Some(0),
+            "The turn timeout must be positive"
+        );
+    }
+}
+
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Move {
+    /// The number of the turn, which counts the skipped turns as well.
+    pub turn: u64,
+    pub account_id: AccountId,
+    pub value: String,
+    pub block_height: BlockHeight,
+}
+
+/// The turns of the game in progress with the append-only move log. The room owner pays for the
+/// turns like for the rest of the room, while every player pays for their moves until the room is
+/// removed.
+#[derive(Clone, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Game {
+    /// The seat order, fixed from the players when the game starts.
+    pub seats: Vec<AccountId>,
+    /// The seat of the current turn.
+    pub seat: u32,
+    pub turn: u64,
+    pub turn_started_at: BlockHeight,
+    /// The players who have forfeited. Their turns are passed, like the turns of the players who
+    /// have left the room.
+    pub forfeited: Vec<AccountId>,
+    #[serde(skip)]
+    pub moves: Vec<Move>,
+}
+
+impl Game {
+    fn new(seats: Vec<AccountId>) -> Self {
+        Self {
+            seats,
+            seat: 0,
+            turn: 0,
+            turn_started_at: env::block_height(),
+            forfeited: Vec::new(),
+            moves: Vec::new(),
+        }
+    }
+
+    fn is_seat_active(&self, room: &Room, seat: usize) -> bool {
+        let account_id = &self.seats[seat];
+        room.players.contains(account_id) && !self.forfeited.contains(account_id)
+    }
+
+    /// Returns the first active seat starting from the given one, if any.
+    fn active_seat_from(&self, room: &Room, seat: usize) -> Option<usize> {
+        (0..self.seats.len())
+            .map(|idx| (seat + idx) % self.seats.len())
+            .find(|seat| self.is_seat_active(room, *seat))
+    }
+
+    /// Returns the seat of the player who moves now. The seats of the players who have left the
+    /// room are passed.
+    fn current_seat(&self, room: &Room) -> Option<usize> {
+        self.active_seat_from(room, self.seat as usize)
+    }
+
+    /// Passes the turn from the seat to the next active one.
+    fn next_turn(&mut self, room: &Room, seat: usize) {
+        if let Some(next_seat) = self.active_seat_from(room, seat + 1) {
+            self.seat = next_seat as u32;
+        }
+        self.turn += 1;
+        self.turn_started_at = env::block_height();
+    }
+}
+
+impl Contract {
+    /// Fixes the seat order of the game from the players of the room. The owner pays for the game.
+    pub(crate) fn internal_start_turns(&mut self, room_id: &RoomId) {
+        let room = self.rooms.get(room_id).unwrap();
+        let owner_id = room.owner_id.clone();
+        let game = Game::new(room.players.clone());
+
+        let mut account = self.internal_get_account(&owner_id);
+        account.start_storage_tracker();
+
+        self.games.insert(*room_id, game);
+        self.games.flush();
+
+        account.stop_storage_tracker();
+        self.internal_set_account(&owner_id, account);
+    }
+
+    /// Returns the room in progress with its game.
+    fn internal_get_game(&self, room_id: &RoomId) -> (&Room, &Game) {
+        let room = self.rooms.get(room_id).expect("Room id not found");
+        room.assert_status(&[RoomStatus::InProgress]);
+        let game = self.games.get(room_id).expect("The game has no turns");
+        (room, game)
+    }
+
+    /// Applies the change to the game of the room. The payer pays for the change.
+    fn internal_update_game(
+        &mut self,
+        room_id: &RoomId,
+        payer_id: &AccountId,
+        update: impl FnOnce(&mut Game, &Room),
+    ) {
+        let room = self.rooms.get(room_id).unwrap();
+
+        let mut account = self.internal_get_account(payer_id);
+        account.start_storage_tracker();
+
+        update(self.games.get_mut(room_id).unwrap(), room);
+        self.games.flush();
+
+        account.stop_storage_tracker();
+        self.internal_set_account(payer_id, account);
+
+        self.rooms.get_mut(room_id).unwrap().touch();
+    }
+
+    /// Removes the moves of the game, if any, and every player gets back the storage of their
+    /// moves. The moves of the unregistered accounts are removed without the refund.
+    pub(crate) fn internal_remove_moves_with_refund(&mut self, room_id: &RoomId) {
+        let mut player_ids: Vec<AccountId> = match self.games.get(room_id) {
+            None => return,
+            Some(game) => game.moves.iter().map(|x| x.account_id.clone()).collect(),
+        };
+        player_ids.sort();
+        player_ids.dedup();
+
+        for player_id in player_ids {
+            let mut account = self.internal_get_account(&player_id);
+            account.start_storage_tracker();
+
+            let game = self.games.get_mut(room_id).unwrap();
+            game.moves.retain(|x| x.account_id.ne(&player_id));
+            self.games.flush();
+
+            account.stop_storage_tracker();
+            self.internal_set_account(&player_id, account);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Appends the move of the current player to the move log and passes the turn to the next
+    /// player. The player pays for the move.
+    pub fn submit_move(&mut self, room_id: RoomId, r#move: String) {
+        self.assert_room_not_paused(&room_id);
+        let account_id = predecessor_account_id();
+        let (room, game) = self.internal_get_game(&room_id);
+        let config = &self.internal_get_app(&room.app_name).turns;
+        require!(
+            r#move.len() <= config.max_move_length as usize,
+            "The move is too long"
+        );
+        require!(
+            game.moves.len() < config.max_moves as usize,
+            "The move log is full"
+        );
+        let seat = game
+            .current_seat(room)
+            .filter(|seat| game.seats[*seat].eq(&account_id))
+            .expect("It's not the turn of the account");
+        let turn = game.turn;
+
+        let new_move = Move {
+            turn,
+            account_id: account_id.clone(),
+            value: r#move,
+            block_height: env::block_height(),
+        };
+        self.internal_update_game(&room_id, &account_id, |game, room| {
+            game.moves.push(new_move);
+            game.next_turn(room, seat);
+        });
+
+        let room = self.rooms.get(&room_id).unwrap();
+        events::emit_move_submitted(room, &account_id, turn);
+    }
+
+    /// Passes the turn of the player who hasn't moved within the turn timeout of the app. The
+    /// player forfeits when the app says so. Any player in the room can call it, and the owner
+    /// pays for the change.
+    pub fn time_out_turn(&mut self, room_id: RoomId) {
+        self.assert_room_not_paused(&room_id);
+        let (room, game) = self.internal_get_game(&room_id);
+        let config = &self.internal_get_app(&room.app_name).turns;
+        let turn_timeout_blocks = config
+            .turn_timeout_blocks
+            .expect("The turns of the app don't time out");
+        let timeout_action = config.timeout_action;
+        if !room.players.contains(&predecessor_account_id()) {
+            panic!("Only the players can time out the turn")
+        }
+        require!(
+            env::block_height() >= game.turn_started_at + turn_timeout_blocks,
+            "The turn hasn't timed out"
+        );
+        let seat = game.current_seat(room).expect("No player can move");
+        let player_id = game.seats[seat].clone();
+        let turn = game.turn;
+        let owner_id = room.owner_id.clone();
+
+        self.internal_update_game(&room_id, &owner_id, |game, room| {
+            if timeout_action == TimeoutAction::Forfeit {
+                game.forfeited.push(player_id.clone());
+            }
+            game.next_turn(room, seat);
+        });
+
+        let room = self.rooms.get(&room_id).unwrap();
+        match timeout_action {
+            TimeoutAction::Skip => events::emit_turn_skipped(room, &player_id, turn),
+            TimeoutAction::Forfeit => events::emit_player_forfeited(room, &player_id, turn),
+        }
+    }
+
+    /// Returns the turns of the game without the move log, if the game has them.
+    pub fn get_game(&self, room_id: RoomId) -> Option<Game> {
+        self.games.get(&room_id).cloned()
+    }
+
+    /// Returns the player who moves now, if any.
+    pub fn get_current_player(&self, room_id: RoomId) -> Option<AccountId> {
+        let room = self.rooms.get(&room_id).expect("Room id not found");
+        let game = self.games.get(&room_id)?;
+        game.current_seat(room).map(|seat| game.seats[seat].clone())
+    }
+
+    /// Returns the moves of the game from the first one.
+    pub fn get_moves(
+        &self,
+        room_id: RoomId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Move> {
+        let moves = match self.games.get(&room_id) {
+            None => return Vec::new(),
+            Some(game) => &game.moves,
+        };
+        let start = from_index.map(|from_index| from_index.0).unwrap_or(0);
+
+        moves
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(moves.len() as u64) as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppConfig;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs};
+
+    const TURN_TIMEOUT_BLOCKS: BlockHeight = 10;
+
+    fn setup_game(contract: &mut Contract, timeout_action: TimeoutAction) -> RoomId {
+        let config = AppConfig {
+            turns: Some(TurnConfig {
+                max_moves: 4,
+                max_move_length: 8,
+                turn_timeout_blocks: Some(TURN_TIMEOUT_BLOCKS),
+                timeout_action,
+            }),
+            ..app_config()
+        };
+        let room_id = setup_app_room(contract, config, room_config(APP_NAME, 3), 3);
+        set_context(&accounts(0), 0);
+        contract.start_game(room_id, APP_NAME.to_string());
+        room_id
+    }
+
+    fn submit_move(contract: &mut Contract, room_id: RoomId, account_id: &AccountId, value: &str) {
+        set_context(account_id, 0);
+        contract.submit_move(room_id, value.to_string());
+    }
+
+    #[test]
+    fn test_submit_moves_in_seat_order() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+        assert_eq!(
+            contract.get_game(room_id).unwrap().seats,
+            vec![accounts(0), accounts(1), accounts(2)]
+        );
+
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"move_submitted","data":[{"room_id":0,"app_name":"app","account_id":"alice","turn":0}]}"#
+            ]
+        );
+        submit_move(&mut contract, room_id, &accounts(1), "e5");
+        submit_move(&mut contract, room_id, &accounts(2), "d4");
+        assert_eq!(contract.get_current_player(room_id), Some(accounts(0)));
+        assert!(used_bytes(&contract, &accounts(0)) > owner_used_bytes);
+        assert!(used_bytes(&contract, &accounts(1)) > player_used_bytes);
+
+        let moves = contract.get_moves(room_id, Some(U128(1)), Some(5));
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].account_id, accounts(1));
+        assert_eq!(moves[0].value, "e5");
+        assert_eq!(moves[1].turn, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "It's not the turn of the account")]
+    fn test_submit_move_out_of_turn() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        submit_move(&mut contract, room_id, &accounts(1), "e4");
+    }
+
+    #[test]
+    #[should_panic(expected = "The move is too long")]
+    fn test_submit_move_too_long() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        submit_move(&mut contract, room_id, &accounts(0), "123456789");
+    }
+
+    #[test]
+    #[should_panic(expected = "The move log is full")]
+    fn test_submit_move_log_full() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        for idx in 0..5 {
+            submit_move(&mut contract, room_id, &accounts(idx % 3), "x");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The room status Finished doesn't allow the action")]
+    fn test_submit_move_after_game() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+        contract.finish_game(room_id, APP_NAME.to_string());
+
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+    }
+
+    #[test]
+    fn test_left_player_is_passed() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+        assert_eq!(contract.get_current_player(room_id), Some(accounts(2)));
+    }
+
+    #[test]
+    fn test_time_out_turn_skips_player() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        set_context_at_block(&accounts(1), 0, TURN_TIMEOUT_BLOCKS);
+        contract.time_out_turn(room_id);
+        assert_eq!(contract.get_current_player(room_id), Some(accounts(1)));
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"room","version":"1.0.0","event":"turn_skipped","data":[{"room_id":0,"app_name":"app","account_id":"alice","turn":0}]}"#
+            ]
+        );
+
+        submit_move(&mut contract, room_id, &accounts(1), "e4");
+        let moves = contract.get_moves(room_id, None, None);
+        assert_eq!(moves[0].turn, 1);
+    }
+
+    #[test]
+    fn test_time_out_turn_forfeits_player() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Forfeit);
+
+        set_context_at_block(&accounts(2), 0, TURN_TIMEOUT_BLOCKS);
+        contract.time_out_turn(room_id);
+        assert_eq!(
+            contract.get_game(room_id).unwrap().forfeited,
+            vec![accounts(0)]
+        );
+
+        submit_move(&mut contract, room_id, &accounts(1), "e4");
+        submit_move(&mut contract, room_id, &accounts(2), "e5");
+        assert_eq!(contract.get_current_player(room_id), Some(accounts(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "The turn hasn't timed out")]
+    fn test_time_out_turn_too_early() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+
+        set_context_at_block(&accounts(1), 0, TURN_TIMEOUT_BLOCKS - 1);
+        contract.time_out_turn(room_id);
+    }
+
+    #[test]
+    fn test_game_follows_ownership() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+
+        set_context(&accounts(0), 0);
+        contract.transfer_ownership(room_id, accounts(1));
+        assert_eq!(contract.get_moves(room_id, None, None).len(), 1);
+        submit_move(&mut contract, room_id, &accounts(1), "e5");
+
+        set_context(&accounts(1), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.get_game(room_id).is_none());
+        assert_eq!(used_bytes(&contract, &accounts(2)), 0);
+    }
+
+    #[test]
+    fn test_player_pays_for_move() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+        let owner_used_bytes = used_bytes(&contract, &accounts(0));
+        let player_used_bytes = used_bytes(&contract, &accounts(1));
+
+        submit_move(&mut contract, room_id, &accounts(1), "e5");
+        assert_eq!(used_bytes(&contract, &accounts(0)), owner_used_bytes);
+        assert!(used_bytes(&contract, &accounts(1)) > player_used_bytes);
+
+        // The move stays in the log and paid by the player after they leave the room.
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert!(used_bytes(&contract, &accounts(1)) > 0);
+        assert_eq!(contract.get_moves(room_id, None, None).len(), 2);
+
+        set_context(&accounts(0), 0);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account that pays for the storage outside")]
+    fn test_storage_unregister_force_of_mover() {
+        let mut contract = new_contract();
+        let room_id = setup_game(&mut contract, TimeoutAction::Skip);
+        submit_move(&mut contract, room_id, &accounts(0), "e4");
+        submit_move(&mut contract, room_id, &accounts(1), "e5");
+
+        set_context(&accounts(1), 1);
+        contract.storage_unregister(Some(true));
+    }
+}
@@ -0,0 +1,553 @@
+use crate::account_rooms::AccountRooms;
+use crate::admin::ContractConfig;
+use crate::app::AppMetadata;
+use crate::attestation::ResultAttestationConfig;
+use crate::room_state::{RoomState, RoomStateConfig};
+use crate::turns::TurnConfig;
+use crate::*;
+use near_sdk::{BlockHeight, StorageUsage};
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+/// The version of the rooms stored before the tags, see [`RoomV0`].
+const ROOM_V0: u8 = 0;
+/// The version the rooms are stored with.
+const ROOM_CURRENT: u8 = 1;
+
+/// The stored room. The previous layouts are converted whenever the room is read, so the rooms
+/// don't have to be enumerated on the upgrade. The room is written back in the current layout on
+/// its next change.
+#[derive(Clone)]
+pub enum VersionedRoom {
+    Current(Room),
+}
+
+impl BorshSerialize for VersionedRoom {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            VersionedRoom::Current(room) => {
+                BorshSerialize::serialize(&ROOM_CURRENT, writer)?;
+                BorshSerialize::serialize(room, writer)
+            }
+        }
+    }
+}
+
+impl BorshDeserialize for VersionedRoom {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        match <u8 as BorshDeserialize>::deserialize(buf)? {
+            ROOM_V0 => Ok(VersionedRoom::Current(
+                <RoomV0 as BorshDeserialize>::deserialize(buf)?.into(),
+            )),
+            ROOM_CURRENT => Ok(VersionedRoom::Current(
+                <Room as BorshDeserialize>::deserialize(buf)?,
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unknown room version",
+            )),
+        }
+    }
+}
+
+impl From<Room> for VersionedRoom {
+    fn from(room: Room) -> Self {
+        VersionedRoom::Current(room)
+    }
+}
+
+impl Deref for VersionedRoom {
+    type Target = Room;
+
+    fn deref(&self) -> &Room {
+        match self {
+            VersionedRoom::Current(room) => room,
+        }
+    }
+}
+
+impl DerefMut for VersionedRoom {
+    fn deref_mut(&mut self) -> &mut Room {
+        match self {
+            VersionedRoom::Current(room) => room,
+        }
+    }
+}
+
+/// The room before the tags.
+#[derive(Clone, BorshDeserialize, BorshSerialize)]
+pub struct RoomV0 {
+    room_id: RoomId,
+    app_name: AppName,
+    name: String,
+    owner_id: AccountId,
+    players: Vec<AccountId>,
+    invited_players: Vec<AccountId>,
+    bans: Vec<Ban>,
+    moderators: Vec<AccountId>,
+    muted_players: Vec<AccountId>,
+    player_limit: usize,
+    is_hidden: bool,
+    access_code_hash: Option<CryptoHash>,
+    status: RoomStatus,
+    result: Option<Vec<AccountId>>,
+    result_status: ResultStatus,
+    attestations: Vec<ResultAttestation>,
+    entry_fee: U128,
+    fee_token_id: Option<AccountId>,
+    payout_table: Vec<u32>,
+    nft_gate: Option<NftGate>,
+    prize_pool: U128,
+    finished_at: U64,
+    created_at: U64,
+    last_activity: U64,
+    extra: Option<String>,
+}
+
+impl From<RoomV0> for Room {
+    fn from(room: RoomV0) -> Self {
+        Room {
+            room_id: room.room_id,
+            app_name: room.app_name,
+            name: room.name,
+            tags: Vec::new(),
+            owner_id: room.owner_id,
+            players: room.players,
+            invited_players: room.invited_players,
+            bans: room.bans,
+            moderators: room.moderators,
+            muted_players: room.muted_players,
+            player_limit: room.player_limit,
+            is_hidden: room.is_hidden,
+            access_code_hash: room.access_code_hash,
+            status: room.status,
+            result: room.result,
+            result_status: room.result_status,
+            attestations: room.attestations,
+            entry_fee: room.entry_fee,
+            fee_token_id: room.fee_token_id,
+            payout_table: room.payout_table,
+            nft_gate: room.nft_gate,
+            prize_pool: room.prize_pool,
+            finished_at: room.finished_at,
+            created_at: room.created_at,
+            last_activity: room.last_activity,
+            extra: room.extra,
+        }
+    }
+}
+
+/// The stored account. The previous layouts are converted whenever the account is read, so the
+/// accounts don't have to be enumerated on the upgrade.
+#[derive(Clone, BorshDeserialize, BorshSerialize)]
+pub enum VersionedAccount {
+    Current(Account),
+}
+
+impl From<Account> for VersionedAccount {
+    fn from(account: Account) -> Self {
+        VersionedAccount::Current(account)
+    }
+}
+
+impl From<VersionedAccount> for Account {
+    fn from(account: VersionedAccount) -> Self {
+        match account {
+            VersionedAccount::Current(account) => account,
+        }
+    }
+}
+
+/// The app before the turns.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct OldApp {
+    owner_id: AccountId,
+    metadata: AppMetadata,
+    default_player_limit: usize,
+    max_player_limit: usize,
+    allow_hidden_rooms: bool,
+    extra_schema: Option<String>,
+    queue_ttl_blocks: BlockHeight,
+    max_queue_size: u32,
+    rating_window: u32,
+    referee_id: Option<AccountId>,
+    result_attestation: Option<ResultAttestationConfig>,
+    accepted_tokens: Vec<AccountId>,
+    room_ttl: U64,
+    cleanup_bounty_percent: u32,
+    max_rooms_per_account: u32,
+    room_state: RoomStateConfig,
+    is_paused: bool,
+}
+
+impl From<OldApp> for App {
+    fn from(app: OldApp) -> Self {
+        App {
+            owner_id: app.owner_id,
+            metadata: app.metadata,
+            default_player_limit: app.default_player_limit,
+            max_player_limit: app.max_player_limit,
+            allow_hidden_rooms: app.allow_hidden_rooms,
+            extra_schema: app.extra_schema,
+            queue_ttl_blocks: app.queue_ttl_blocks,
+            max_queue_size: app.max_queue_size,
+            rating_window: app.rating_window,
+            referee_id: app.referee_id,
+            result_attestation: app.result_attestation,
+            accepted_tokens: app.accepted_tokens,
+            room_ttl: app.room_ttl,
+            cleanup_bounty_percent: app.cleanup_bounty_percent,
+            max_rooms_per_account: app.max_rooms_per_account,
+            room_state: app.room_state,
+            turns: TurnConfig::default(),
+            is_paused: app.is_paused,
+        }
+    }
+}
+
+/// The state layout before the turns.
+#[derive(BorshDeserialize)]
+#[cfg_attr(test, derive(BorshSerialize))]
+pub(crate) struct OldContract {
+    rooms: LookupMap<RoomId, VersionedRoom>,
+    apps: UnorderedMap<AppName, OldApp>,
+    accounts: LookupMap<AccountId, VersionedAccount>,
+    legacy_accounts: LookupMap<AccountId, Account>,
+    available_rooms_per_app: LookupMap<AppName, UnorderedSet<RoomId>>,
+    rooms_per_app: LookupMap<AppName, UnorderedSet<RoomId>>,
+    rooms_by_fill: LookupMap<AppName, TreeMap<(u64, RoomId), ()>>,
+    account_rooms: LookupMap<AccountId, AccountRooms>,
+    room_states: LookupMap<RoomId, RoomState>,
+    storage_deposits: LookupMap<AccountId, Balance>,
+    invites: LookupMap<AccountId, Vec<Invite>>,
+    queue_entries: LookupMap<AccountId, QueueEntry>,
+    app_queues: LookupMap<AppName, Vec<AccountId>>,
+    ratings: LookupMap<AppName, UnorderedMap<AccountId, Rating>>,
+    leaderboards: LookupMap<AppName, Vec<LeaderboardEntry>>,
+    ft_claims: LookupMap<(AccountId, AccountId), Balance>,
+    app_bans: LookupMap<AppName, UnorderedMap<AccountId, AppBan>>,
+    next_room_id: u64,
+    owner_id: AccountId,
+    config: ContractConfig,
+    is_paused: bool,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Upgrades the state of the previous release. The contract account calls it in the same
+    /// transaction as the deploy. The apps are converted in this call, so they must fit the gas
+    /// limit. The app owners pay for the change of their storage.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let mut old: OldContract = env::state_read().expect("The contract is not initialized");
+
+        // The apps keep their prefix, so all of them are taken out before they are written back.
+        let mut storage_changes: Vec<(AccountId, i64)> = Vec::new();
+        let mut old_apps = Vec::new();
+        let app_names: Vec<AppName> = old.apps.keys().cloned().collect();
+        for app_name in app_names {
+            let storage_usage = env::storage_usage();
+            let app = old.apps.remove(&app_name).unwrap();
+            old.apps.flush();
+            storage_changes.push((app.owner_id.clone(), storage_change(storage_usage)));
+            old_apps.push((app_name, app));
+        }
+
+        let mut apps = UnorderedMap::new(AppRegistry);
+        for (app_name, app) in old_apps {
+            let storage_usage = env::storage_usage();
+            let app: App = app.into();
+            let app_owner_id = app.owner_id.clone();
+            apps.insert(app_name, app);
+            apps.flush();
+            storage_changes.push((app_owner_id, storage_change(storage_usage)));
+        }
+
+        let mut contract = Self {
+            rooms: old.rooms,
+            apps,
+            accounts: old.accounts,
+            legacy_accounts: old.legacy_accounts,
+            available_rooms_per_app: old.available_rooms_per_app,
+            rooms_per_app: old.rooms_per_app,
+            rooms_by_fill: old.rooms_by_fill,
+            account_rooms: old.account_rooms,
+            room_states: old.room_states,
+            games: LookupMap::new(Games),
+            storage_deposits: old.storage_deposits,
+            invites: old.invites,
+            queue_entries: old.queue_entries,
+            app_queues: old.app_queues,
+            ratings: old.ratings,
+            leaderboards: old.leaderboards,
+            ft_claims: old.ft_claims,
+            app_bans: old.app_bans,
+            next_room_id: old.next_room_id,
+            owner_id: old.owner_id,
+            config: old.config,
+            is_paused: old.is_paused,
+        };
+        // The storage change is applied as is, since the migration can't be refused.
+        for (account_id, bytes) in storage_changes {
+            if let Some(mut account) = contract.internal_get_account_opt(&account_id) {
+                account.used_bytes = (account.used_bytes as i64 + bytes).max(0) as StorageUsage;
+                contract.internal_set_account(&account_id, account);
+            }
+        }
+        contract
+    }
+}
+
+/// Returns the bytes added to the contract storage since the given usage, negative if released.
+fn storage_change(storage_usage: StorageUsage) -> i64 {
+    env::storage_usage() as i64 - storage_usage as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enumerable::{RoomSort, RoomSortKey};
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+
+    impl From<App> for OldApp {
+        fn from(app: App) -> Self {
+            OldApp {
+                owner_id: app.owner_id,
+                metadata: app.metadata,
+                default_player_limit: app.default_player_limit,
+                max_player_limit: app.max_player_limit,
+                allow_hidden_rooms: app.allow_hidden_rooms,
+                extra_schema: app.extra_schema,
+                queue_ttl_blocks: app.queue_ttl_blocks,
+                max_queue_size: app.max_queue_size,
+                rating_window: app.rating_window,
+                referee_id: app.referee_id,
+                result_attestation: app.result_attestation,
+                accepted_tokens: app.accepted_tokens,
+                room_ttl: app.room_ttl,
+                cleanup_bounty_percent: app.cleanup_bounty_percent,
+                max_rooms_per_account: app.max_rooms_per_account,
+                room_state: app.room_state,
+                is_paused: app.is_paused,
+            }
+        }
+    }
+
+    impl From<Room> for RoomV0 {
+        fn from(room: Room) -> Self {
+            RoomV0 {
+                room_id: room.room_id,
+                app_name: room.app_name,
+                name: room.name,
+                owner_id: room.owner_id,
+                players: room.players,
+                invited_players: room.invited_players,
+                bans: room.bans,
+                moderators: room.moderators,
+                muted_players: room.muted_players,
+                player_limit: room.player_limit,
+                is_hidden: room.is_hidden,
+                access_code_hash: room.access_code_hash,
+                status: room.status,
+                result: room.result,
+                result_status: room.result_status,
+                attestations: room.attestations,
+                entry_fee: room.entry_fee,
+                fee_token_id: room.fee_token_id,
+                payout_table: room.payout_table,
+                nft_gate: room.nft_gate,
+                prize_pool: room.prize_pool,
+                finished_at: room.finished_at,
+                created_at: room.created_at,
+                last_activity: room.last_activity,
+                extra: room.extra,
+            }
+        }
+    }
+
+    /// Charges the account for the storage change since the given usage.
+    fn charge(contract: &mut Contract, account_id: &AccountId, storage_usage: StorageUsage) {
+        let mut account = contract.internal_get_account(account_id);
+        account.used_bytes =
+            (account.used_bytes as i64 + storage_change(storage_usage)) as StorageUsage;
+        contract.internal_set_account(account_id, account);
+    }
+
+    /// Rewrites the app in the previous layout, removes the games of the rooms and moves the
+    /// accounts to the layout before the versioning.
+    fn downgrade(
+        mut contract: Contract,
+        app_name: &str,
+        room_ids: &[RoomId],
+        account_ids: &[AccountId],
+    ) -> OldContract {
+        let app_name = app_name.to_string();
+        let storage_usage = env::storage_usage();
+        let app = contract.apps.remove(&app_name).unwrap();
+        contract.apps.flush();
+        let app_owner_id = app.owner_id.clone();
+        let mut apps = UnorderedMap::new(AppRegistry);
+        apps.insert(app_name, OldApp::from(app));
+        apps.flush();
+        charge(&mut contract, &app_owner_id, storage_usage);
+
+        for room_id in room_ids {
+            let storage_usage = env::storage_usage();
+            contract.games.remove(room_id);
+            contract.games.flush();
+            let owner_id = contract.rooms.get(room_id).unwrap().owner_id.clone();
+            charge(&mut contract, &owner_id, storage_usage);
+        }
+
+        for account_id in account_ids {
+            let account = contract.internal_get_account(account_id);
+            contract.accounts.remove(account_id);
+            contract.legacy_accounts.insert(account_id.clone(), account);
+        }
+
+        OldContract {
+            rooms: contract.rooms,
+            apps,
+            accounts: contract.accounts,
+            legacy_accounts: contract.legacy_accounts,
+            available_rooms_per_app: contract.available_rooms_per_app,
+            rooms_per_app: contract.rooms_per_app,
+            rooms_by_fill: contract.rooms_by_fill,
+            account_rooms: contract.account_rooms,
+            room_states: contract.room_states,
+            storage_deposits: contract.storage_deposits,
+            invites: contract.invites,
+            queue_entries: contract.queue_entries,
+            app_queues: contract.app_queues,
+            ratings: contract.ratings,
+            leaderboards: contract.leaderboards,
+            ft_claims: contract.ft_claims,
+            app_bans: contract.app_bans,
+            next_room_id: contract.next_room_id,
+            owner_id: contract.owner_id,
+            config: contract.config,
+            is_paused: contract.is_paused,
+        }
+    }
+
+    fn migrated_contract() -> (Contract, RoomId) {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        let room_id = contract.create_room(room_config(APP_NAME, 3));
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        set_context(&accounts(2), DEPOSIT);
+        contract.storage_deposit(None, None);
+
+        let old = downgrade(contract, APP_NAME, &[room_id], &[accounts(1), accounts(2)]);
+        env::state_write(&old);
+        drop(old);
+
+        (Contract::migrate(), room_id)
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (contract, room_id) = migrated_contract();
+
+        let app = contract.get_app(APP_NAME.to_string()).unwrap();
+        assert_eq!(app.turns.max_moves, TurnConfig::default().max_moves);
+        assert_eq!(app.room_state.max_keys, RoomStateConfig::default().max_keys);
+        assert!(contract.room_states.get(&room_id).is_some());
+        assert!(contract.get_game(room_id).is_none());
+        assert_eq!(
+            contract
+                .search_rooms(APP_NAME.to_string(), None, None, None, None)
+                .rooms
+                .len(),
+            1
+        );
+        let sort = RoomSort {
+            key: RoomSortKey::FillRatio,
+            descending: false,
+        };
+        let page = contract.search_rooms(APP_NAME.to_string(), None, Some(sort), None, None);
+        assert_eq!(page.rooms[0].room_id, room_id);
+        assert!(contract.legacy_accounts.get(&accounts(1)).is_some());
+        assert!(contract.storage_balance_of(accounts(2)).is_some());
+    }
+
+    #[test]
+    fn test_room_v0_is_converted_on_read() {
+        let mut contract = new_contract();
+        register_app(&mut contract, &accounts(5), APP_NAME);
+        set_context(&accounts(0), DEPOSIT);
+        let mut config = room_config(APP_NAME, 3);
+        config.tags = Some(vec!["chess".to_string()]);
+        let room_id = contract.create_room(config);
+        let room = Room::clone(contract.rooms.get(&room_id).unwrap());
+        env::state_write(&contract);
+        drop(contract);
+
+        let key = [Rooms.try_to_vec().unwrap(), room_id.try_to_vec().unwrap()].concat();
+        let room_v0 = (ROOM_V0, RoomV0::from(room)).try_to_vec().unwrap();
+        env::storage_write(&key, &room_v0);
+        let mut contract: Contract = env::state_read().unwrap();
+        assert!(contract.rooms.get(&room_id).unwrap().tags.is_empty());
+
+        set_context(&accounts(1), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        assert_eq!(
+            contract.rooms.get(&room_id).unwrap().players,
+            vec![accounts(0), accounts(1)]
+        );
+    }
+
+    #[test]
+    fn test_room_is_stored_as_current() {
+        let mut contract = new_contract();
+        let room_id = setup_room(&mut contract, 3, 1);
+        let room = contract.rooms.get(&room_id).unwrap();
+
+        let bytes = room.try_to_vec().unwrap();
+        assert_eq!(bytes[0], ROOM_CURRENT);
+        let room = VersionedRoom::try_from_slice(&bytes).unwrap();
+        assert_eq!(room.room_id, room_id);
+    }
+
+    #[test]
+    fn test_legacy_account_is_upgraded_on_write() {
+        let (mut contract, _) = migrated_contract();
+        let legacy_used_bytes = used_bytes(&contract, &accounts(2));
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.storage_deposit(None, None);
+        assert!(contract.legacy_accounts.get(&accounts(2)).is_none());
+        assert!(contract.accounts.get(&accounts(2)).is_some());
+        assert_eq!(used_bytes(&contract, &accounts(2)), legacy_used_bytes);
+    }
+
+    #[test]
+    fn test_migrated_room_is_joined_and_removed() {
+        let (mut contract, room_id) = migrated_contract();
+
+        set_context(&accounts(2), DEPOSIT);
+        contract.join(room_id, APP_NAME.to_string(), None);
+        set_context(&accounts(1), 0);
+        contract.leave(room_id, APP_NAME.to_string());
+        assert_eq!(used_bytes(&contract, &accounts(1)), 0);
+
+        set_context(&accounts(0), 0);
+        contract.set_room_state(room_id, "key".to_string(), "value".to_string());
+        contract.start_game(room_id, APP_NAME.to_string());
+        contract.submit_move(room_id, "e4".to_string());
+        assert_eq!(contract.get_moves(room_id, None, None).len(), 1);
+        contract.remove(room_id, APP_NAME.to_string());
+        assert!(contract.rooms.get(&room_id).is_none());
+        assert!(contract.room_states.get(&room_id).is_none());
+        assert!(contract.get_game(room_id).is_none());
+        assert_eq!(
+            contract.get_number_of_available_rooms(APP_NAME.to_string()),
+            0
+        );
+    }
+}